@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+use cortex_m_rt::{entry, exception};
+use defmt_rtt as _;
+use embassy_boot_rp::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+/// Swaps in an uploaded firmware, or the previous one if the new firmware never confirmed
+/// itself, then jumps to the active slot
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+
+    // a swap takes a while, the watchdog restarts it if it hangs
+    let flash = WatchdogFlash::<FLASH_SIZE>::start(p.FLASH, p.WATCHDOG, Duration::from_secs(8));
+    let flash = Mutex::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader = BootLoader::prepare::<_, _, _, 2048>(config);
+
+    unsafe { bootloader.load(embassy_rp::flash::FLASH_BASE as u32 + active_offset) }
+}
+
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
@@ -8,14 +8,21 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 
-use probe_rs::flashing::Format::Bin;
-use probe_rs::flashing::{BinOptions, DownloadOptions, FlashLoader};
+#[allow(dead_code)]
+#[path = "src/crc32.rs"]
+mod crc32;
+#[allow(dead_code)]
+#[path = "src/assets/table.rs"]
+mod table;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use probe_rs::flashing::{DownloadOptions, FlashLoader};
 use probe_rs::probe::list::Lister;
 use probe_rs::{MemoryInterface, Permissions};
 use std::fs::{File, read_to_string};
-use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::{env, fs};
 
 macro_rules! p {
@@ -45,84 +52,139 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 
     println!("cargo:rerun-if-changed=flash_files");
-    download_files().unwrap();
+    let binding = read_to_string("flash_files").unwrap();
+    let files = binding.lines().collect::<Vec<_>>();
+    for file in files.iter() {
+        println!("cargo:rerun-if-changed={}", file);
+    }
+
+    // included by the firmware with `embed-assets`
+    let image = static_image(&files).unwrap();
+    fs::write(out.join("static.bin"), &image).unwrap();
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        return;
+    }
+    if env::var_os("CARGO_FEATURE_PACKAGE_ASSETS").is_some() {
+        package_files(&image).unwrap();
+    } else {
+        download_files(&image).unwrap();
+    }
 }
 
 const STATIC_START: u64 = 0x10300000;
-fn download_files() -> Result<bool, anyhow::Error> {
-    let out_dir = env::var("OUT_DIR")?;
+/// `STATIC` in `memory.x`
+const STATIC_SIZE: usize = 512 * 1024;
+/// RP2350 ARM secure image, see the UF2 family list
+const UF2_FAMILY_ID: u32 = 0xe48bff59;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
 
-    let binding = read_to_string("flash_files")?;
-    let files = binding.lines().collect::<Vec<_>>();
+/// Contents of the `STATIC` region: an asset table of the files, see `src/assets/table.rs`
+fn static_image(files: &[&str]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut contents = Vec::new();
+    for file in files.iter() {
+        contents.push((file.to_string(), fs::read(file)?));
+    }
+    let image = table::build(&contents, gzip).map_err(anyhow::Error::msg)?;
+    if image.len() > STATIC_SIZE {
+        anyhow::bail!(
+            "assets are {} bytes, at most {STATIC_SIZE} fit",
+            image.len()
+        );
+    }
+    Ok(image)
+}
+
+fn uf2(data: &[u8], address: u32) -> Vec<u8> {
+    const PAYLOAD: usize = 256;
+    let blocks = data.len().div_ceil(PAYLOAD);
+    let mut uf2 = Vec::with_capacity(blocks * 512);
+    for (i, chunk) in data.chunks(PAYLOAD).enumerate() {
+        let mut block = [0u8; 512];
+        let words = [
+            0x0A324655,
+            0x9E5D5157,
+            // family id present
+            0x00002000,
+            address + (i * PAYLOAD) as u32,
+            PAYLOAD as u32,
+            i as u32,
+            blocks as u32,
+            UF2_FAMILY_ID,
+        ];
+        for (j, word) in words.iter().enumerate() {
+            block[4 * j..4 * j + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        block[32..32 + chunk.len()].copy_from_slice(chunk);
+        block[508..].copy_from_slice(&0x0AB16F30u32.to_le_bytes());
+        uf2.extend_from_slice(&block);
+    }
+    uf2
+}
 
+/// Writes `static.bin` and `static.uf2` next to the firmware, to be flashed with picotool or
+/// by copying the UF2 onto the drive of the boot ROM
+fn package_files(image: &[u8]) -> Result<(), anyhow::Error> {
+    // OUT_DIR is target/<triple>/<profile>/build/<crate>-<hash>/out
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let target_dir = out_dir.ancestors().nth(3).unwrap();
+    fs::write(target_dir.join("static.bin"), image)?;
+    fs::write(
+        target_dir.join("static.uf2"),
+        uf2(image, STATIC_START as u32),
+    )?;
+    p!(
+        "STATIC region written to {}",
+        target_dir.join("static.uf2").display()
+    );
+    Ok(())
+}
+
+fn download_files(image: &[u8]) -> Result<bool, anyhow::Error> {
     let lister = Lister::new();
     let probes = lister.list_all();
-    let probe = probes[0].open()?;
+    let Some(probe) = probes.first() else {
+        anyhow::bail!(
+            "no debug probe found, build with the `embed-assets` or `package-assets` feature instead"
+        );
+    };
+    let probe = probe.open()?;
     let mut session = probe.attach("RP235x", Permissions::default())?;
 
     let mut core = session.core(0)?;
 
-    let mut hasher = DefaultHasher::new();
-    files.hash(&mut hasher);
-    for file in files.iter() {
-        fs::metadata(file)?.modified()?.hash(&mut hasher);
-    }
-    let hash = hasher.finish();
-
-    let expected = [STATIC_START, hash];
-    let mut header = [0u64; 2];
-    core.read_64(STATIC_START, &mut header)?;
+    // the header holds the format version and the CRC-32 of the table and file contents, so
+    // the region is only rewritten when the files actually changed
+    let expected = table::Header::parse(image).map_err(|err| anyhow::anyhow!("{err:?}"))?;
+    let mut header = [0u8; table::HEADER_SIZE];
+    core.read_8(STATIC_START, &mut header)?;
     drop(core);
 
-    let missing: Vec<&&str> = files
-        .iter()
-        .filter(|f| !Path::new(&out_dir).join(f).exists())
-        .collect();
-
-    if header == expected && missing.is_empty() {
-        return Ok(false);
+    match table::Header::parse(&header) {
+        Ok(header) if header == expected => return Ok(false),
+        Ok(header) => p!(
+            "assets changed, checksum {:08x} on the chip, {:08x} built. Reflashing...",
+            header.crc,
+            expected.crc
+        ),
+        Err(err) => p!("no usable assets on the chip ({:?}). Flashing...", err),
     }
 
-    if header != expected {
-        p!(
-            "header mismatch, expected {:?}, got {:?}. Reflashing...",
-            expected,
-            header
-        );
-    } else {
-        p!("Missing file(s): {:?}", missing);
-    }
-
-    let mut i = STATIC_START;
     let mut loader = FlashLoader::new(
         session.target().memory_map.to_vec(),
         session.target().source().clone(),
     );
-    loader.add_data(i, &expected[0].to_le_bytes())?;
-    i += 8;
-    loader.add_data(i, &expected[1].to_le_bytes())?;
-    i += 8;
-
-    for file in files.iter() {
-        println!("cargo:rerun-if-changed={}", file);
-
-        let size = fs::metadata(file)?.len();
-        let options = BinOptions {
-            base_address: Some(i),
-            skip: 0,
-        };
-        loader.load_image(&mut session, &mut File::open(file)?, Bin(options), None)?;
-
-        let path = Path::new(&out_dir).join(file);
-        let content = format!("core::slice::from_raw_parts({} as *const u8, {})", i, size);
-        fs::create_dir_all(path.parent().unwrap())?;
-        fs::write(path, content)?;
-
-        i += size;
-    }
+    loader.add_data(STATIC_START, image)?;
 
     let mut options = DownloadOptions::default();
-    options.do_chip_erase = true;
+    // only the sectors covered by the image are erased, the bootloader, firmware and uploaded
+    // assets stay
+    options.do_chip_erase = false;
     options.keep_unwritten_bytes = true;
     options.verify = true;
 
@@ -0,0 +1,338 @@
+//! Request and response bodies of the HTTP API and the WebSocket stream, with the checks and
+//! status codes the firmware and the simulator answer with
+
+use crate::auth::{AuthError, Token};
+use crate::command::CommandError;
+use crate::control::{Deadman, EStop, SafeAction, Status, Trip};
+use crate::legs::in_range;
+use crate::servo::{MotionState, SERVO_COUNT};
+use crate::settings::{MAX_HOSTNAME_LEN, PowerManagement, Settings, SettingsError};
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+const MAX_ANGLE: f32 = 180.0;
+
+/// Status code and message of a rejected request, sent as [ErrorBody]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: &'static str,
+}
+
+impl ApiError {
+    pub const UNAUTHORIZED: ApiError = ApiError::new(401, "unauthorized");
+
+    pub const fn new(status: u16, message: &'static str) -> Self {
+        ApiError { status, message }
+    }
+
+    pub fn body(&self) -> ErrorBody<'static> {
+        ErrorBody {
+            error: self.message,
+        }
+    }
+}
+
+impl From<CommandError> for ApiError {
+    fn from(err: CommandError) -> Self {
+        match err {
+            CommandError::QueueFull => ApiError::new(503, "command queue full"),
+            CommandError::EStopped => ApiError::new(409, "emergency stop latched"),
+            CommandError::InvalidServo => ApiError::new(404, "unknown servo"),
+            CommandError::InvalidLeg => ApiError::new(404, "unknown leg"),
+            CommandError::Unreachable => ApiError::new(400, "position not reachable"),
+            CommandError::Dropped => ApiError::new(409, "command dropped"),
+        }
+    }
+}
+
+impl From<SettingsError> for ApiError {
+    fn from(err: SettingsError) -> Self {
+        match err {
+            SettingsError::Invalid(message) => ApiError::new(400, message),
+            SettingsError::TooLarge => ApiError::new(400, "settings too large"),
+            SettingsError::Flash => ApiError::new(500, "writing flash failed"),
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::WrongPassword => ApiError::new(401, "wrong password"),
+            AuthError::Locked(_) => ApiError::new(429, "too many failed logins, try again later"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody<'a> {
+    pub error: &'a str,
+}
+
+#[derive(Copy, Clone, Deserialize, Serialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Position {
+    pub fn to_array(&self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    pub fn from_array(v: [f32; 3]) -> Self {
+        Position {
+            x: v[0],
+            y: v[1],
+            z: v[2],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if in_range(self.to_array()) {
+            Ok(())
+        } else {
+            Err(ApiError::new(400, "coordinates out of range"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Joints {
+    /// Angle per servo in degree, `null` keeps the joint where it is
+    pub angles: [Option<f32>; SERVO_COUNT],
+}
+
+impl Joints {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let valid = self
+            .angles
+            .iter()
+            .flatten()
+            .all(|a| a.is_finite() && (0.0..=MAX_ANGLE).contains(a));
+        if valid {
+            Ok(())
+        } else {
+            Err(ApiError::new(400, "angle out of range"))
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Accepted {
+    pub seq: u32,
+}
+
+#[derive(Serialize)]
+pub struct DeadmanState {
+    pub timeout_ms: u32,
+    pub action: SafeAction,
+    pub armed: bool,
+    pub tripped: Option<Trip>,
+}
+
+impl From<Deadman> for DeadmanState {
+    fn from(deadman: Deadman) -> Self {
+        DeadmanState {
+            timeout_ms: deadman.timeout_ms,
+            action: deadman.action,
+            armed: deadman.last_seen_ms.is_some(),
+            tripped: deadman.tripped,
+        }
+    }
+}
+
+/// Tasks that have to check in regularly for the firmware's watchdog to be fed
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Supervised {
+    Servo,
+    Net,
+    Cyw43,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetCause {
+    PowerOn,
+    /// Reset requested by software
+    Forced,
+    /// Watchdog ran out, with the task that stopped checking in if it was known
+    Watchdog(Option<Supervised>),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WifiMode {
+    /// Joined an existing network, address from DHCP
+    Station,
+    /// Own access point with a static address
+    AccessPoint,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NetworkInfo {
+    pub mode: WifiMode,
+    pub address: [u8; 4],
+}
+
+#[derive(Serialize)]
+pub struct State {
+    pub motion: Status,
+    pub estop: Option<EStop>,
+    pub deadman: DeadmanState,
+    pub reset: ResetCause,
+    pub network: Option<NetworkInfo>,
+    pub uptime_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct NetworkState {
+    pub current: Option<NetworkInfo>,
+    pub hostname: String<MAX_HOSTNAME_LEN>,
+    /// Stored network joined on the next boot, the password is never returned
+    pub station_ssid: Option<String<32>>,
+}
+
+impl NetworkState {
+    pub fn new(settings: &Settings, current: Option<NetworkInfo>) -> Self {
+        NetworkState {
+            current,
+            hostname: String::try_from(settings.hostname()).unwrap_or_default(),
+            station_ssid: settings
+                .station
+                .as_ref()
+                .map(|station| station.ssid.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Hostname {
+    pub hostname: String<MAX_HOSTNAME_LEN>,
+}
+
+#[derive(Serialize)]
+pub struct AccessPointView {
+    pub ssid: String<32>,
+    pub channel: u8,
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+    pub pool_start: [u8; 4],
+    pub pool_size: u8,
+    pub lease_time_s: u32,
+}
+
+/// Stored settings without any passwords
+#[derive(Serialize)]
+pub struct SettingsView {
+    pub hostname: String<MAX_HOSTNAME_LEN>,
+    pub station_ssid: Option<String<32>>,
+    pub access_point: AccessPointView,
+    pub power_management: PowerManagement,
+}
+
+impl From<&Settings> for SettingsView {
+    fn from(settings: &Settings) -> Self {
+        let ap = &settings.access_point;
+        SettingsView {
+            hostname: String::try_from(settings.hostname()).unwrap_or_default(),
+            station_ssid: settings
+                .station
+                .as_ref()
+                .map(|station| station.ssid.clone()),
+            access_point: AccessPointView {
+                ssid: ap.ssid.clone(),
+                channel: ap.channel,
+                address: ap.address,
+                prefix_len: ap.prefix_len,
+                pool_start: ap.pool_start,
+                pool_size: ap.pool_size,
+                lease_time_s: ap.lease_time_s,
+            },
+            power_management: settings.power_management,
+        }
+    }
+}
+
+/// Fields to change, everything left out keeps its stored value
+#[derive(Deserialize)]
+pub struct SettingsUpdate {
+    pub hostname: Option<String<MAX_HOSTNAME_LEN>>,
+    pub ap_ssid: Option<String<32>>,
+    pub ap_password: Option<String<64>>,
+    pub ap_channel: Option<u8>,
+    pub ap_address: Option<[u8; 4]>,
+    pub ap_prefix_len: Option<u8>,
+    pub ap_pool_start: Option<[u8; 4]>,
+    pub ap_pool_size: Option<u8>,
+    pub ap_lease_time_s: Option<u32>,
+    pub power_management: Option<PowerManagement>,
+    pub password: Option<String<64>>,
+}
+
+impl SettingsUpdate {
+    /// Changes `settings`, the caller validates and stores them
+    pub fn apply(self, settings: &mut Settings) {
+        let ap = &mut settings.access_point;
+        if self.hostname.is_some() {
+            settings.hostname = self.hostname;
+        }
+        ap.ssid = self.ap_ssid.unwrap_or(ap.ssid.clone());
+        ap.password = self.ap_password.unwrap_or(ap.password.clone());
+        ap.channel = self.ap_channel.unwrap_or(ap.channel);
+        ap.address = self.ap_address.unwrap_or(ap.address);
+        ap.prefix_len = self.ap_prefix_len.unwrap_or(ap.prefix_len);
+        ap.pool_start = self.ap_pool_start.unwrap_or(ap.pool_start);
+        ap.pool_size = self.ap_pool_size.unwrap_or(ap.pool_size);
+        ap.lease_time_s = self.ap_lease_time_s.unwrap_or(ap.lease_time_s);
+        settings.power_management = self.power_management.unwrap_or(settings.power_management);
+        if self.password.is_some() {
+            settings.password = self.password;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    pub password: String<64>,
+}
+
+#[derive(Serialize)]
+pub struct Session {
+    pub token: Token,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FirmwareState {
+    Confirmed,
+    /// Swapped in by the bootloader, reverted on the next reset unless confirmed
+    Trial,
+}
+
+#[derive(Serialize)]
+pub struct Firmware {
+    pub version: &'static str,
+    pub state: FirmwareState,
+}
+
+/// WebSocket message from the client, every field is optional. An empty object is a heartbeat.
+#[derive(Deserialize)]
+pub struct StreamCommand {
+    pub pose: Option<Position>,
+    /// Velocity of the foot target in mm/s, replaces the previous one
+    pub velocity: Option<Position>,
+}
+
+/// WebSocket message to the client
+#[derive(Serialize)]
+pub struct StreamState {
+    pub state: MotionState,
+    pub pose: Option<Position>,
+    pub queued: usize,
+    pub estop: bool,
+}
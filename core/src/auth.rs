@@ -0,0 +1,274 @@
+//! Sessions for the control routes and the login lockout per client address. The firmware and
+//! the simulator keep an [Auth] behind a lock and pass in the time and fresh random tokens.
+
+use heapless::{String, Vec};
+
+pub const TOKEN_LEN: usize = 16;
+/// Oldest session is dropped when another client logs in
+const MAX_SESSIONS: usize = 4;
+/// Clients with failed logins that are tracked, the least recently locked one is dropped
+const MAX_CLIENTS: usize = 8;
+/// Failed logins allowed before logins of the client are locked
+const MAX_FAILURES: u32 = 5;
+/// First lockout, doubled with every further lockout until the next successful login
+const LOCKOUT_MS: u64 = 30_000;
+const MAX_LOCKOUT_MS: u64 = 10 * 60 * 1000;
+const COOKIE: &str = "token=";
+
+pub type Token = String<{ 2 * TOKEN_LEN }>;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthError {
+    WrongPassword,
+    /// Too many failed attempts, try again after this many ms
+    Locked(u64),
+}
+
+struct Session<A> {
+    token: [u8; TOKEN_LEN],
+    /// Last address the session was used from, UDP control is accepted from it
+    address: A,
+}
+
+/// Failed logins of one client address
+struct Client<A> {
+    address: A,
+    failures: u32,
+    lockouts: u32,
+    /// Uptime in ms until which logins are refused
+    locked_until: u64,
+}
+
+/// Sessions and failed logins, `A` is the address type of the network stack
+pub struct Auth<A> {
+    password: String<64>,
+    sessions: Vec<Session<A>, MAX_SESSIONS>,
+    clients: Vec<Client<A>, MAX_CLIENTS>,
+}
+
+impl<A> Default for Auth<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compares in constant time so the password can't be guessed from response times
+fn matches(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn encode(token: &[u8; TOKEN_LEN]) -> Token {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut s = Token::new();
+    for b in token {
+        s.push(HEX[(b >> 4) as usize] as char).ok();
+        s.push(HEX[(b & 0xF) as usize] as char).ok();
+    }
+    s
+}
+
+fn decode(s: &str) -> Option<[u8; TOKEN_LEN]> {
+    if s.len() != 2 * TOKEN_LEN {
+        return None;
+    }
+    let mut token = [0; TOKEN_LEN];
+    for (i, b) in token.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(token)
+}
+
+impl<A> Auth<A> {
+    /// No login succeeds until [Auth::set_password] was called
+    pub const fn new() -> Self {
+        Self {
+            password: String::new(),
+            sessions: Vec::new(),
+            clients: Vec::new(),
+        }
+    }
+
+    /// Sets the password for control routes, open sessions stay valid
+    pub fn set_password(&mut self, password: &str) {
+        self.password = String::try_from(password).unwrap_or_default();
+    }
+
+    /// Number of open sessions
+    pub fn sessions(&self) -> usize {
+        self.sessions.len()
+    }
+}
+
+impl<A: Copy + PartialEq> Auth<A> {
+    /// Failed logins of `address`, a new entry replaces the least recently locked client
+    fn client(&mut self, address: A) -> &mut Client<A> {
+        let index = match self.clients.iter().position(|c| c.address == address) {
+            Some(index) => index,
+            None => {
+                let client = Client {
+                    address,
+                    failures: 0,
+                    lockouts: 0,
+                    locked_until: 0,
+                };
+                if self.clients.is_full() {
+                    let oldest = (0..self.clients.len())
+                        .min_by_key(|&i| self.clients[i].locked_until)
+                        .unwrap();
+                    self.clients.swap_remove(oldest);
+                }
+                self.clients.push(client).ok();
+                self.clients.len() - 1
+            }
+        };
+        &mut self.clients[index]
+    }
+
+    /// Checks the password and opens a session with the random `token`. Failed attempts lock
+    /// logins from the same address for a while, so one client can't lock out the others.
+    pub fn login(
+        &mut self,
+        peer: A,
+        password: &str,
+        now_ms: u64,
+        token: [u8; TOKEN_LEN],
+    ) -> Result<Token, AuthError> {
+        let locked_until = self
+            .clients
+            .iter()
+            .find(|c| c.address == peer)
+            .map(|c| c.locked_until);
+        if let Some(until) = locked_until
+            && now_ms < until
+        {
+            return Err(AuthError::Locked(until - now_ms));
+        }
+
+        if self.password.is_empty() || !matches(password.as_bytes(), self.password.as_bytes()) {
+            let client = self.client(peer);
+            // a lockout that has passed starts the count over
+            if client.failures >= MAX_FAILURES {
+                client.failures = 0;
+            }
+            client.failures += 1;
+            if client.failures >= MAX_FAILURES {
+                let doublings = client.lockouts.min(16);
+                let lockout = (LOCKOUT_MS << doublings).min(MAX_LOCKOUT_MS);
+                client.lockouts += 1;
+                client.locked_until = now_ms + lockout;
+                return Err(AuthError::Locked(lockout));
+            }
+            return Err(AuthError::WrongPassword);
+        }
+        self.clients.retain(|c| c.address != peer);
+
+        if self.sessions.is_full() {
+            self.sessions.remove(0);
+        }
+        self.sessions
+            .push(Session {
+                token,
+                address: peer,
+            })
+            .ok();
+        Ok(encode(&token))
+    }
+
+    pub fn logout(&mut self, token: &str) {
+        let Some(token) = decode(token) else {
+            return;
+        };
+        self.sessions.retain(|s| s.token != token);
+    }
+
+    /// Checks the session and moves it to the address it is used from
+    pub fn is_valid(&mut self, token: &str, peer: A) -> bool {
+        let Some(token) = decode(token) else {
+            return false;
+        };
+        match self.sessions.iter_mut().find(|s| matches(&s.token, &token)) {
+            Some(session) => {
+                session.address = peer;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a client at `address` holds a session, for the UDP control which has no tokens
+    pub fn is_authorized(&self, address: A) -> bool {
+        self.sessions.iter().any(|s| s.address == address)
+    }
+}
+
+/// Extracts the session token from an `Authorization: Bearer` header or the `token` cookie,
+/// the cookie lets browsers authorize WebSocket upgrades
+pub fn token<'a>(authorization: Option<&'a str>, cookie: Option<&'a str>) -> Option<&'a str> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            cookie?
+                .split(';')
+                .find_map(|c| c.trim().strip_prefix(COOKIE))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth<u8> {
+        let mut auth = Auth::new();
+        auth.set_password("robodogg");
+        auth
+    }
+
+    #[test]
+    fn sessions_follow_their_client() {
+        let mut auth = auth();
+        let token = auth.login(1, "robodogg", 0, [7; TOKEN_LEN]).unwrap();
+        assert!(auth.is_authorized(1));
+        assert!(auth.is_valid(&token, 2));
+        assert!(!auth.is_authorized(1));
+        assert!(auth.is_authorized(2));
+
+        auth.logout(&token);
+        assert!(!auth.is_valid(&token, 2));
+    }
+
+    #[test]
+    fn lockout_is_per_client_and_doubles() {
+        let mut auth = auth();
+        for _ in 1..MAX_FAILURES {
+            assert_eq!(
+                auth.login(1, "wrong", 0, [0; TOKEN_LEN]),
+                Err(AuthError::WrongPassword)
+            );
+        }
+        assert_eq!(
+            auth.login(1, "wrong", 0, [0; TOKEN_LEN]),
+            Err(AuthError::Locked(LOCKOUT_MS))
+        );
+        assert_eq!(
+            auth.login(1, "robodogg", 1000, [0; TOKEN_LEN]),
+            Err(AuthError::Locked(LOCKOUT_MS - 1000))
+        );
+        assert!(auth.login(2, "robodogg", 1000, [0; TOKEN_LEN]).is_ok());
+
+        for _ in 0..MAX_FAILURES {
+            auth.login(1, "wrong", LOCKOUT_MS, [0; TOKEN_LEN]).ok();
+        }
+        assert_eq!(
+            auth.login(1, "robodogg", LOCKOUT_MS, [0; TOKEN_LEN]),
+            Err(AuthError::Locked(2 * LOCKOUT_MS))
+        );
+    }
+
+    #[test]
+    fn token_from_header_or_cookie() {
+        assert_eq!(token(Some("Bearer abc"), Some("token=def")), Some("abc"));
+        assert_eq!(token(None, Some("theme=dark; token=def")), Some("def"));
+        assert_eq!(token(Some("Basic abc"), None), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! Commands for the servo task, queued by the HTTP, WebSocket and UDP handlers
+
+use crate::servo::SERVO_COUNT;
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ServoTask {
+    CALIBRATION(u8, f32),
+    MOVE(f32, f32, f32),
+    /// Moves a single leg, the other ones keep their position
+    LEG(u8, f32, f32, f32),
+    /// Joint angles in degree, `None` keeps the joint where it is
+    JOINTS([Option<f32>; SERVO_COUNT]),
+    HOME,
+    RELAX,
+    STOP,
+}
+
+impl ServoTask {
+    pub fn priority(&self) -> Priority {
+        match self {
+            ServoTask::STOP => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ServoTask {
+    fn format(&self, fmt: defmt::Formatter) {
+        use defmt::write;
+        match self {
+            ServoTask::CALIBRATION(servo, pos) => {
+                write!(fmt, "CALIBRATION({} -> {})", servo, pos)
+            }
+            ServoTask::MOVE(x, y, z) => {
+                write!(fmt, "MOVE({})", (x, y, z))
+            }
+            ServoTask::LEG(leg, x, y, z) => {
+                write!(fmt, "LEG({} -> {})", leg, (x, y, z))
+            }
+            ServoTask::JOINTS(angles) => {
+                write!(fmt, "JOINTS({})", angles)
+            }
+            ServoTask::HOME => {
+                write!(fmt, "HOME")
+            }
+            ServoTask::RELAX => {
+                write!(fmt, "RELAX")
+            }
+            ServoTask::STOP => {
+                write!(fmt, "STOP")
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// Handled before anything else, even while a motion is still running
+    High,
+    /// Executed in order, each one after the previous motion has finished
+    Normal,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandError {
+    QueueFull,
+    Unreachable,
+    InvalidServo,
+    InvalidLeg,
+    /// Removed from the queue by a higher priority command
+    Dropped,
+    /// Rejected while the emergency stop is latched
+    EStopped,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandResult {
+    Queued,
+    Running,
+    Done,
+    Failed(CommandError),
+}
+
+impl CommandResult {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, CommandResult::Done | CommandResult::Failed(_))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Command {
+    pub seq: u32,
+    pub task: ServoTask,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stop_skips_the_queue() {
+        assert_eq!(ServoTask::STOP.priority(), Priority::High);
+        assert_eq!(ServoTask::HOME.priority(), Priority::Normal);
+        assert_eq!(
+            ServoTask::MOVE(0.0, 160.0, 0.0).priority(),
+            Priority::Normal
+        );
+    }
+
+    #[test]
+    fn finished_results() {
+        assert!(CommandResult::Done.is_finished());
+        assert!(CommandResult::Failed(CommandError::Dropped).is_finished());
+        assert!(!CommandResult::Queued.is_finished());
+        assert!(!CommandResult::Running.is_finished());
+    }
+}
@@ -0,0 +1,619 @@
+//! Scheduling of the control loop: command queues, result history, emergency stop and deadman.
+//! `servo_task` on the robot and the simulator drive [ControlLoop] with their own clock and
+//! waiting, every time is an uptime in ms.
+
+use crate::command::{Command, CommandError, CommandResult, Priority, ServoTask};
+use crate::legs::Legs;
+use crate::servo::{MotionState, SERVO_COUNT};
+use core::str::FromStr;
+use embedded_hal::pwm::SetDutyCycle;
+use heapless::{Deque, Vec};
+use serde::Serialize;
+
+/// Period of the control loop
+pub const TICK_MS: u64 = 5;
+const QUEUE_SIZE: usize = 16;
+const PRIORITY_QUEUE_SIZE: usize = 4;
+/// Number of results kept around for polling, older ones get overwritten
+const RESULT_HISTORY: usize = 32;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EStopSource {
+    Http,
+    Button,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EStopMode {
+    /// Keep every servo powered at its current position
+    Freeze,
+    /// Switch every PWM output off
+    Relax,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EStop {
+    pub source: EStopSource,
+    pub mode: EStopMode,
+    /// Uptime in ms when the stop was triggered
+    pub at_ms: u64,
+}
+
+/// What the control loop does once the lease has run out
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SafeAction {
+    Home,
+    Relax,
+}
+
+impl SafeAction {
+    pub fn task(&self) -> ServoTask {
+        match self {
+            SafeAction::Home => ServoTask::HOME,
+            SafeAction::Relax => ServoTask::RELAX,
+        }
+    }
+}
+
+impl FromStr for SafeAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "home" => Ok(SafeAction::Home),
+            "relax" => Ok(SafeAction::Relax),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Trip {
+    /// Uptime in ms when the deadman tripped
+    pub at_ms: u64,
+    /// Time since the last heartbeat or setpoint
+    pub silent_ms: u64,
+    pub action: SafeAction,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Deadman {
+    /// Lease length in ms, 0 disables the deadman
+    pub timeout_ms: u32,
+    pub action: SafeAction,
+    /// Uptime in ms of the last heartbeat, `None` while disarmed
+    pub last_seen_ms: Option<u64>,
+    pub tripped: Option<Trip>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionEvent {
+    Started(u32),
+    Finished(u32, CommandResult),
+    StateChanged(MotionState),
+    /// The deadman lease expired and the safe action was taken
+    SafeStop(SafeAction),
+    EStop(EStopMode),
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    pub state: MotionState,
+    pub joints: [MotionState; SERVO_COUNT],
+    /// Sequence id of the command currently being executed
+    pub command: Option<u32>,
+    /// Motion commands waiting in the queue
+    pub queued: usize,
+    pub estop: Option<EStop>,
+}
+
+/// Everything the handlers share with the control loop, kept behind a lock by the caller
+pub struct Scheduler {
+    motion_queue: Deque<Command, QUEUE_SIZE>,
+    priority_queue: Deque<Command, PRIORITY_QUEUE_SIZE>,
+    setpoint: Option<[f32; 3]>,
+    next_seq: u32,
+    /// Empty slots are `None`, so no sequence id is reported before it was submitted
+    results: [Option<(u32, CommandResult)>; RESULT_HISTORY],
+    estop: Option<EStop>,
+    deadman: Deadman,
+    status: Status,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub const fn new() -> Self {
+        Self {
+            motion_queue: Deque::new(),
+            priority_queue: Deque::new(),
+            setpoint: None,
+            next_seq: 1,
+            results: [None; RESULT_HISTORY],
+            estop: None,
+            deadman: Deadman {
+                timeout_ms: 1000,
+                action: SafeAction::Home,
+                last_seen_ms: None,
+                tripped: None,
+            },
+            status: Status {
+                state: MotionState::Idle,
+                joints: [MotionState::Idle; SERVO_COUNT],
+                command: None,
+                queued: 0,
+                estop: None,
+            },
+        }
+    }
+
+    /// Queues a task for the control loop and returns its sequence id
+    pub fn submit(&mut self, task: ServoTask) -> Result<u32, CommandError> {
+        if task.priority() == Priority::Normal && self.estop.is_some() {
+            return Err(CommandError::EStopped);
+        }
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+        let command = Command { seq, task };
+
+        let sent = match task.priority() {
+            Priority::High => self.priority_queue.push_back(command),
+            Priority::Normal => self.motion_queue.push_back(command),
+        };
+        if sent.is_err() {
+            self.set_result(seq, CommandResult::Failed(CommandError::QueueFull));
+            return Err(CommandError::QueueFull);
+        }
+        self.set_result(seq, CommandResult::Queued);
+        debug!("Queued: {}", command);
+        Ok(seq)
+    }
+
+    /// Streams a pose target for teleoperation. Only the latest one is kept and it replaces
+    /// the running motion instead of waiting for it to finish. Only streamed motion arms the
+    /// deadman, queued commands run to the end without heartbeats.
+    pub fn stream(&mut self, pose: [f32; 3], now_ms: u64) -> Result<(), CommandError> {
+        if self.estop.is_some() {
+            return Err(CommandError::EStopped);
+        }
+        self.deadman.last_seen_ms = Some(now_ms);
+        self.setpoint = Some(pose);
+        Ok(())
+    }
+
+    /// Returns the last known result of a command, `None` if it is unknown or too old
+    pub fn result(&self, seq: u32) -> Option<CommandResult> {
+        let (stored, result) = self.results[seq as usize % RESULT_HISTORY]?;
+        (stored == seq).then_some(result)
+    }
+
+    /// Number of motion commands waiting to be executed
+    pub fn pending(&self) -> usize {
+        self.motion_queue.len()
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            queued: self.pending(),
+            estop: self.estop,
+            ..self.status
+        }
+    }
+
+    /// Latches the emergency stop. A later trigger can only escalate from freeze to relax.
+    pub fn trigger_estop(&mut self, source: EStopSource, mode: EStopMode, now_ms: u64) {
+        if let Some(latched) = self.estop
+            && (latched.mode == EStopMode::Relax || mode == EStopMode::Freeze)
+        {
+            return;
+        }
+        warn!("Emergency stop from {}: {}", source, mode);
+        self.estop = Some(EStop {
+            source,
+            mode,
+            at_ms: now_ms,
+        });
+    }
+
+    /// Releases the latch, motion commands are accepted again afterwards
+    pub fn reset_estop(&mut self) {
+        if self.estop.take().is_some() {
+            info!("Emergency stop reset");
+            self.clear();
+        }
+    }
+
+    pub fn estop(&self) -> Option<EStop> {
+        self.estop
+    }
+
+    pub fn deadman(&self) -> Deadman {
+        self.deadman
+    }
+
+    pub fn configure_deadman(&mut self, timeout_ms: u32, action: SafeAction) {
+        self.deadman.timeout_ms = timeout_ms;
+        self.deadman.action = action;
+    }
+
+    /// Extends the lease only if it is armed, so heartbeats alone never start motion supervision
+    pub fn heartbeat(&mut self, now_ms: u64) {
+        if self.deadman.last_seen_ms.is_some() {
+            self.deadman.last_seen_ms = Some(now_ms);
+        }
+    }
+
+    /// Stores the results and motion state of an iteration of the [ControlLoop]
+    pub fn record(&mut self, report: &Report) {
+        for &(seq, result) in &report.results {
+            self.set_result(seq, result);
+        }
+        self.status.state = report.state;
+        self.status.joints = report.joints;
+        self.status.command = report.command;
+    }
+
+    fn set_result(&mut self, seq: u32, result: CommandResult) {
+        self.results[seq as usize % RESULT_HISTORY] = Some((seq, result));
+    }
+
+    /// Drops every queued motion command and the streamed setpoint
+    fn clear(&mut self) {
+        self.setpoint = None;
+        while let Some(command) = self.motion_queue.pop_front() {
+            self.set_result(command.seq, CommandResult::Failed(CommandError::Dropped));
+        }
+    }
+
+    /// Motion commands are only taken when `accept_motion` is set, priority commands always are
+    fn next(&mut self, accept_motion: bool) -> Option<Command> {
+        match self.priority_queue.pop_front() {
+            Some(command) => Some(command),
+            None if accept_motion => self.motion_queue.pop_front(),
+            None => None,
+        }
+    }
+
+    /// Returns the action to take if the lease has expired. Disarms until the next setpoint.
+    fn check_deadman(&mut self, now_ms: u64) -> Option<SafeAction> {
+        let deadman = &mut self.deadman;
+        let last_seen_ms = deadman.last_seen_ms?;
+        if deadman.timeout_ms == 0 {
+            return None;
+        }
+        let silent_ms = now_ms.saturating_sub(last_seen_ms);
+        if silent_ms < deadman.timeout_ms as u64 {
+            return None;
+        }
+
+        warn!("Deadman tripped after {} ms, {}", silent_ms, deadman.action);
+        deadman.last_seen_ms = None;
+        deadman.tripped = Some(Trip {
+            at_ms: now_ms,
+            silent_ms,
+            action: deadman.action,
+        });
+        Some(deadman.action)
+    }
+}
+
+/// What to do after a [ControlLoop::step]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    /// A command or setpoint was taken, step again without waiting
+    Busy,
+    /// Wait for a new command, or tick once [TICK_MS] have passed
+    Wait,
+}
+
+/// What one iteration takes from the [Scheduler], see [ControlLoop::plan]
+pub struct Plan {
+    /// Newly latched emergency stop to apply
+    estop: Option<EStopMode>,
+    /// The deadman tripped
+    safe_stop: Option<SafeAction>,
+    command: Option<Command>,
+    setpoint: Option<[f32; 3]>,
+}
+
+/// What one iteration did, handed back to the [Scheduler] with [Scheduler::record] and
+/// published once its lock is released
+pub struct Report {
+    step: Step,
+    results: Vec<(u32, CommandResult), 4>,
+    events: Vec<MotionEvent, 8>,
+    state: MotionState,
+    joints: [MotionState; SERVO_COUNT],
+    command: Option<u32>,
+}
+
+impl Report {
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    /// Motion events in the order they happened
+    pub fn events(&self) -> impl Iterator<Item = MotionEvent> + '_ {
+        self.events.iter().copied()
+    }
+
+    fn finish(&mut self, seq: u32, result: CommandResult) {
+        self.results.push((seq, result)).ok();
+        self.events.push(MotionEvent::Finished(seq, result)).ok();
+    }
+}
+
+/// The legs and the state the control loop keeps between iterations
+pub struct ControlLoop<P> {
+    legs: Legs<P>,
+    /// Sequence id of the command being executed
+    running: Option<u32>,
+    /// Mode of the latched emergency stop once it has been applied
+    stopped: Option<EStopMode>,
+    state: MotionState,
+}
+
+impl<P: SetDutyCycle> ControlLoop<P> {
+    pub fn new(legs: Legs<P>) -> Self {
+        Self {
+            legs,
+            running: None,
+            stopped: None,
+            state: MotionState::Idle,
+        }
+    }
+
+    pub fn legs(&self) -> &Legs<P> {
+        &self.legs
+    }
+
+    /// One iteration for callers that own the scheduler: [Self::plan], [Self::run] and
+    /// [Scheduler::record], then `publish` for every event
+    pub fn step(
+        &mut self,
+        scheduler: &mut Scheduler,
+        now_ms: u64,
+        publish: &mut impl FnMut(MotionEvent),
+    ) -> Step {
+        let plan = self.plan(scheduler, now_ms);
+        let report = self.run(plan);
+        scheduler.record(&report);
+        report.events().for_each(publish);
+        report.step()
+    }
+
+    /// Takes the emergency stop, the deadman and the next command or setpoint from the
+    /// scheduler. Only this part needs its lock, it touches neither the legs nor the PWM.
+    pub fn plan(&mut self, scheduler: &mut Scheduler, now_ms: u64) -> Plan {
+        let estop = scheduler.estop.map(|e| e.mode);
+        let mut plan = Plan {
+            estop: None,
+            safe_stop: None,
+            command: None,
+            setpoint: None,
+        };
+        if let Some(mode) = estop
+            && self.stopped != Some(mode)
+        {
+            scheduler.clear();
+            scheduler.deadman.last_seen_ms = None;
+            plan.estop = Some(mode);
+        }
+        self.stopped = estop;
+
+        if estop.is_none() {
+            plan.safe_stop = scheduler.check_deadman(now_ms);
+            if plan.safe_stop.is_some() {
+                scheduler.clear();
+            }
+        }
+
+        // only the loop itself moves the legs, so they are still idle when the plan runs
+        let accept_motion = self.legs.is_idle() && estop.is_none() && plan.safe_stop.is_none();
+        plan.command = scheduler.next(accept_motion);
+        match plan.command {
+            Some(command) if command.task == ServoTask::STOP => scheduler.clear(),
+            Some(_) => {}
+            None => plan.setpoint = scheduler.setpoint.take(),
+        }
+        plan
+    }
+
+    /// Applies `plan` to the legs: inverse kinematics and the PWM writes, without the lock
+    pub fn run(&mut self, plan: Plan) -> Report {
+        let mut report = Report {
+            step: Step::Wait,
+            results: Vec::new(),
+            events: Vec::new(),
+            state: self.state,
+            joints: [MotionState::Idle; SERVO_COUNT],
+            command: None,
+        };
+
+        if let Some(mode) = plan.estop {
+            if let Some(prev) = self.running.take() {
+                report.finish(prev, CommandResult::Failed(CommandError::EStopped));
+            }
+            match mode {
+                EStopMode::Freeze => self.legs.hold(),
+                EStopMode::Relax => self.legs.relax(),
+            }
+            report.events.push(MotionEvent::EStop(mode)).ok();
+        }
+
+        if let Some(action) = plan.safe_stop {
+            if let Some(prev) = self.running.take() {
+                report.finish(prev, CommandResult::Failed(CommandError::Dropped));
+            }
+            self.legs.execute(action.task()).ok();
+            report.events.push(MotionEvent::SafeStop(action)).ok();
+        }
+
+        if self.legs.is_idle()
+            && let Some(seq) = self.running.take()
+        {
+            report.finish(seq, CommandResult::Done);
+        }
+
+        if let Some(Command { seq, task }) = plan.command {
+            info!("Task {}: {}", seq, task);
+            if let Some(prev) = self.running.take() {
+                report.finish(prev, CommandResult::Failed(CommandError::Dropped));
+            }
+            match self.legs.execute(task) {
+                Ok(()) => {
+                    report.results.push((seq, CommandResult::Running)).ok();
+                    report.events.push(MotionEvent::Started(seq)).ok();
+                    self.running = Some(seq);
+                }
+                Err(err) => report.finish(seq, CommandResult::Failed(err)),
+            }
+            report.step = Step::Busy;
+        } else if let Some([x, y, z]) = plan.setpoint {
+            if self.stopped.is_none() {
+                if let Some(prev) = self.running.take() {
+                    report.finish(prev, CommandResult::Failed(CommandError::Dropped));
+                }
+                if let Err(err) = self.legs.execute(ServoTask::MOVE(x, y, z)) {
+                    debug!("Setpoint failed: {}", err);
+                }
+            }
+            report.step = Step::Busy;
+        }
+
+        report.joints = self.legs.states();
+        report.state = MotionState::of(&report.joints);
+        report.command = self.running;
+        if self.state != report.state {
+            debug!("Motion state: {} -> {}", self.state, report.state);
+            report
+                .events
+                .push(MotionEvent::StateChanged(report.state))
+                .ok();
+            self.state = report.state;
+        }
+        report
+    }
+
+    /// Advances the running motion by one period of [TICK_MS]
+    pub fn tick(&mut self) {
+        self.legs.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockPwm;
+    use crate::servo::{Servo, ServoConfig};
+
+    fn control() -> ControlLoop<MockPwm> {
+        ControlLoop::new(Legs::new(
+            ServoConfig::calibration().map(|c| Servo::new(MockPwm::default(), c)),
+        ))
+    }
+
+    /// Steps until the loop waits, then ticks once
+    fn run(control: &mut ControlLoop<MockPwm>, scheduler: &mut Scheduler, now_ms: u64) {
+        while control.step(scheduler, now_ms, &mut |_| {}) == Step::Busy {}
+        control.tick();
+    }
+
+    #[test]
+    fn unused_slots_have_no_result() {
+        let scheduler = Scheduler::new();
+        assert_eq!(scheduler.result(0), None);
+        assert_eq!(scheduler.result(RESULT_HISTORY as u32), None);
+    }
+
+    #[test]
+    fn queued_commands_run_in_order() {
+        let mut scheduler = Scheduler::new();
+        let mut control = control();
+        let first = scheduler.submit(ServoTask::MOVE(0.0, 150.0, 0.0)).unwrap();
+        let second = scheduler.submit(ServoTask::HOME).unwrap();
+
+        assert_eq!(control.step(&mut scheduler, 0, &mut |_| {}), Step::Busy);
+        assert_eq!(scheduler.result(first), Some(CommandResult::Running));
+        assert_eq!(scheduler.result(second), Some(CommandResult::Queued));
+        // the second one waits for the motion, without a deadman tripping in between
+        for ms in (0..5000).step_by(TICK_MS as usize) {
+            run(&mut control, &mut scheduler, ms);
+        }
+        assert_eq!(scheduler.result(first), Some(CommandResult::Done));
+        assert_eq!(scheduler.result(second), Some(CommandResult::Done));
+        assert!(scheduler.deadman().tripped.is_none());
+    }
+
+    #[test]
+    fn stop_drops_the_queue() {
+        let mut scheduler = Scheduler::new();
+        let mut control = control();
+        let running = scheduler.submit(ServoTask::MOVE(0.0, 150.0, 0.0)).unwrap();
+        run(&mut control, &mut scheduler, 0);
+        let queued = scheduler.submit(ServoTask::HOME).unwrap();
+        scheduler.submit(ServoTask::STOP).unwrap();
+        run(&mut control, &mut scheduler, 5);
+
+        let dropped = Some(CommandResult::Failed(CommandError::Dropped));
+        assert_eq!(scheduler.result(running), dropped);
+        assert_eq!(scheduler.result(queued), dropped);
+        assert_eq!(scheduler.pending(), 0);
+    }
+
+    #[test]
+    fn estop_only_escalates() {
+        let mut scheduler = Scheduler::new();
+        scheduler.trigger_estop(EStopSource::Button, EStopMode::Relax, 10);
+        scheduler.trigger_estop(EStopSource::Http, EStopMode::Freeze, 20);
+        let estop = scheduler.estop().unwrap();
+        assert_eq!(
+            (estop.source, estop.mode),
+            (EStopSource::Button, EStopMode::Relax)
+        );
+        assert_eq!(
+            scheduler.submit(ServoTask::HOME),
+            Err(CommandError::EStopped)
+        );
+        assert!(scheduler.submit(ServoTask::STOP).is_ok());
+
+        scheduler.reset_estop();
+        assert!(scheduler.submit(ServoTask::HOME).is_ok());
+    }
+
+    #[test]
+    fn deadman_only_supervises_streamed_motion() {
+        let mut scheduler = Scheduler::new();
+        let mut control = control();
+        scheduler.heartbeat(0);
+        assert_eq!(scheduler.deadman().last_seen_ms, None);
+
+        scheduler.stream([0.0, 150.0, 0.0], 0).unwrap();
+        run(&mut control, &mut scheduler, 0);
+        scheduler.heartbeat(900);
+        run(&mut control, &mut scheduler, 1800);
+        assert!(scheduler.deadman().tripped.is_none());
+
+        let mut events = heapless::Vec::<MotionEvent, 8>::new();
+        control.step(&mut scheduler, 1900, &mut |e| events.push(e).unwrap());
+        let trip = scheduler.deadman().tripped.unwrap();
+        assert_eq!((trip.at_ms, trip.silent_ms), (1900, 1000));
+        assert!(matches!(
+            events[..],
+            [MotionEvent::SafeStop(SafeAction::Home), ..]
+        ));
+        assert_eq!(scheduler.deadman().last_seen_ms, None);
+    }
+}
@@ -0,0 +1,37 @@
+//! Logging through defmt with the `defmt` feature, compiled out otherwise
+
+macro_rules! debug {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        defmt::debug!($fmt $(, $arg)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($fmt $(, &$arg)*);
+    }};
+}
+
+macro_rules! info {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        defmt::info!($fmt $(, $arg)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($fmt $(, &$arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        defmt::warn!($fmt $(, $arg)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($fmt $(, &$arg)*);
+    }};
+}
+
+macro_rules! error {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        defmt::error!($fmt $(, $arg)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($fmt $(, &$arg)*);
+    }};
+}
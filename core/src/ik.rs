@@ -0,0 +1,121 @@
+use core::f32::consts::PI;
+use libm::{acosf, atan2f, sqrtf};
+
+const RAD_TO_DEG: f32 = 180f32 / PI;
+
+pub struct Joint {
+    length: f32,
+}
+
+impl Joint {
+    pub fn new(length: f32) -> Self {
+        Self { length }
+    }
+}
+
+#[allow(unused)]
+pub struct IkSolver {
+    j0: Joint,
+    j1: Joint,
+    j2: Joint,
+}
+
+impl IkSolver {
+    pub fn new(j0: Joint, j1: Joint, j2: Joint) -> Self {
+        Self { j0, j1, j2 }
+    }
+
+    pub fn solve(&self, x: f32, y: f32, z: f32) -> Option<(f32, f32, f32)> {
+        let mut x = x;
+        let mut y = y;
+        let mut a1 = 0.0;
+
+        if z != 0.0 {
+            let len = sqrtf((x * x) + (y * y) + (z * z));
+            let xy_len = sqrtf((x * x) + (y * y));
+
+            let z_angle = atan2f(z, xy_len);
+            a1 = z_angle * RAD_TO_DEG;
+
+            let mul = len / xy_len;
+            x *= mul;
+            y *= mul;
+        }
+
+        let (a2, a3) = self.solve_2d(x, y);
+
+        if !(a1.is_finite() && a2.is_finite() && a3.is_finite()) {
+            return None;
+        }
+
+        Some((a1, a2, a3))
+    }
+
+    fn solve_2d(&self, x: f32, y: f32) -> (f32, f32) {
+        let start_to_end = sqrtf((x * x) + (y * y));
+
+        let num = (start_to_end * start_to_end) + (self.j1.length * self.j1.length)
+            - (self.j2.length * self.j2.length);
+        let denom = 2.0 * start_to_end * self.j1.length;
+        let a1_degree = acosf(num / denom) * RAD_TO_DEG;
+        let a1_offset = atan2f(x, y) * RAD_TO_DEG;
+
+        let a1 = a1_degree + a1_offset;
+        let a2 = 180.0 - (a1_degree * 2.0);
+
+        (a1, a2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solver() -> IkSolver {
+        IkSolver::new(Joint::new(0.0), Joint::new(100.0), Joint::new(100.0))
+    }
+
+    fn assert_close(actual: (f32, f32, f32), expected: (f32, f32, f32)) {
+        let diff = [
+            actual.0 - expected.0,
+            actual.1 - expected.1,
+            actual.2 - expected.2,
+        ];
+        assert!(
+            diff.iter().all(|d| d.abs() < 1e-3),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn solves_straight_down() {
+        // 160 mm below the hip: a 3-4-5 triangle with both links at 100 mm
+        let angle = acosf(0.8) * RAD_TO_DEG;
+        let angles = solver().solve(0.0, 160.0, 0.0).unwrap();
+        assert_close(angles, (0.0, angle, 180.0 - 2.0 * angle));
+    }
+
+    #[test]
+    fn forward_offset_turns_the_thigh() {
+        let (_, straight, knee) = solver().solve(0.0, 160.0, 0.0).unwrap();
+        let (a1, a2, a3) = solver().solve(20.0, 160.0, 0.0).unwrap();
+        assert_eq!(a1, 0.0);
+        assert!(a2 > straight);
+        // the foot is further away, so the knee opens up
+        assert!(a3 > knee);
+    }
+
+    #[test]
+    fn sideways_offset_turns_the_hip() {
+        let (a1, _, _) = solver().solve(0.0, 160.0, 20.0).unwrap();
+        let (b1, _, _) = solver().solve(0.0, 160.0, -20.0).unwrap();
+        assert!(a1 > 0.0);
+        assert!((a1 + b1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_unreachable_positions() {
+        assert_eq!(solver().solve(0.0, 250.0, 0.0), None);
+        assert_eq!(solver().solve(0.0, 0.0, 0.0), None);
+    }
+}
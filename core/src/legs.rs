@@ -0,0 +1,195 @@
+//! Executes [ServoTask]s on the twelve servos, solving foot positions through IK
+
+use crate::command::{CommandError, ServoTask};
+use crate::ik::{IkSolver, Joint};
+use crate::servo::{LEG_COUNT, MotionState, SERVO_COUNT, Servo};
+use embedded_hal::pwm::SetDutyCycle;
+
+/// Largest coordinate accepted for a foot position, in mm
+pub const MAX_REACH: f32 = 300.0;
+
+pub fn in_range(pose: [f32; 3]) -> bool {
+    pose.iter().all(|v| v.is_finite() && v.abs() <= MAX_REACH)
+}
+
+/// Hip, thigh and foot of every leg, in that order
+pub struct Legs<P> {
+    servos: [Servo<P>; SERVO_COUNT],
+    solver: IkSolver,
+    /// Foot target per leg in mm, `None` if the leg was not positioned through IK
+    feet: [Option<[f32; 3]>; LEG_COUNT],
+    ik_failures: u32,
+}
+
+impl<P: SetDutyCycle> Legs<P> {
+    pub fn new(servos: [Servo<P>; SERVO_COUNT]) -> Self {
+        Self {
+            servos,
+            solver: IkSolver::new(Joint::new(0.0), Joint::new(100.0), Joint::new(100.0)),
+            feet: [None; LEG_COUNT],
+            ik_failures: 0,
+        }
+    }
+
+    pub fn execute(&mut self, task: ServoTask) -> Result<(), CommandError> {
+        match task {
+            ServoTask::CALIBRATION(servo, pos) => match self.servos.get_mut(servo as usize) {
+                Some(s) => s.write(pos),
+                None => {
+                    error!("Servo out of range: {}", servo);
+                    return Err(CommandError::InvalidServo);
+                }
+            },
+            ServoTask::MOVE(x, y, z) => {
+                if let Some((a1, a2, a3)) = self.solver.solve(x, y, z) {
+                    debug!("Servo signal: angles={}", (a1, a2, a3));
+                    self.feet = [Some([x, y, z]); LEG_COUNT];
+                    for leg in self.servos.chunks_mut(3) {
+                        leg[0].rotate(a1);
+                        leg[1].rotate(a2);
+                        leg[2].rotate(a3);
+                    }
+                } else {
+                    error!("Not Reachable!");
+                    self.ik_failures += 1;
+                    self.feet = [None; LEG_COUNT];
+                    for s in &mut self.servos {
+                        s.home()
+                    }
+                    return Err(CommandError::Unreachable);
+                }
+            }
+            ServoTask::LEG(leg, x, y, z) => {
+                if leg as usize >= LEG_COUNT {
+                    error!("Leg out of range: {}", leg);
+                    return Err(CommandError::InvalidLeg);
+                }
+                let index = leg as usize;
+                let leg = &mut self.servos[index * 3..index * 3 + 3];
+                if let Some((a1, a2, a3)) = self.solver.solve(x, y, z) {
+                    debug!("Leg signal: angles={}", (a1, a2, a3));
+                    self.feet[index] = Some([x, y, z]);
+                    leg[0].rotate(a1);
+                    leg[1].rotate(a2);
+                    leg[2].rotate(a3);
+                } else {
+                    error!("Not Reachable!");
+                    self.ik_failures += 1;
+                    self.feet[index] = None;
+                    for s in leg {
+                        s.home()
+                    }
+                    return Err(CommandError::Unreachable);
+                }
+            }
+            ServoTask::JOINTS(angles) => {
+                self.feet = [None; LEG_COUNT];
+                for (s, angle) in self.servos.iter_mut().zip(angles) {
+                    if let Some(angle) = angle {
+                        s.rotate(angle)
+                    }
+                }
+            }
+            ServoTask::HOME => {
+                self.feet = [None; LEG_COUNT];
+                for s in &mut self.servos {
+                    s.home()
+                }
+            }
+            ServoTask::RELAX => self.relax(),
+            ServoTask::STOP => self.hold(),
+        }
+        Ok(())
+    }
+
+    /// Advances every running motion by one step
+    pub fn tick(&mut self) {
+        for s in &mut self.servos {
+            s.tick()
+        }
+    }
+
+    /// Stops every servo where it is
+    pub fn hold(&mut self) {
+        for s in &mut self.servos {
+            s.hold()
+        }
+    }
+
+    pub fn relax(&mut self) {
+        for s in &mut self.servos {
+            s.relax()
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.servos.iter().all(Servo::is_idle)
+    }
+
+    pub fn states(&self) -> [MotionState; SERVO_COUNT] {
+        self.servos.each_ref().map(Servo::state)
+    }
+
+    pub fn angles(&self) -> [Option<f32>; SERVO_COUNT] {
+        self.servos.each_ref().map(Servo::angle)
+    }
+
+    pub fn duty(&self) -> [u16; SERVO_COUNT] {
+        self.servos.each_ref().map(Servo::duty)
+    }
+
+    pub fn feet(&self) -> [Option<[f32; 3]>; LEG_COUNT] {
+        self.feet
+    }
+
+    /// Foot positions that could not be solved since boot
+    pub fn ik_failures(&self) -> u32 {
+        self.ik_failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockPwm;
+    use crate::servo::ServoConfig;
+
+    fn legs() -> Legs<MockPwm> {
+        Legs::new(ServoConfig::calibration().map(|c| Servo::new(MockPwm::default(), c)))
+    }
+
+    #[test]
+    fn moves_all_feet() {
+        let mut legs = legs();
+        legs.execute(ServoTask::MOVE(0.0, 150.0, 0.0)).unwrap();
+        assert!(!legs.is_idle());
+        assert_eq!(legs.feet(), [Some([0.0, 150.0, 0.0]); LEG_COUNT]);
+        for _ in 0..1000 {
+            legs.tick();
+        }
+        assert!(legs.is_idle());
+    }
+
+    #[test]
+    fn unreachable_positions_home_the_leg() {
+        let mut legs = legs();
+        legs.execute(ServoTask::LEG(1, 0.0, 150.0, 0.0)).unwrap();
+        assert_eq!(
+            legs.execute(ServoTask::LEG(1, 0.0, 250.0, 0.0)),
+            Err(CommandError::Unreachable)
+        );
+        assert_eq!(legs.feet()[1], None);
+        assert_eq!(legs.ik_failures(), 1);
+        assert_eq!(
+            legs.execute(ServoTask::LEG(4, 0.0, 150.0, 0.0)),
+            Err(CommandError::InvalidLeg)
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_poses() {
+        assert!(in_range([0.0, -300.0, 150.0]));
+        assert!(!in_range([0.0, 0.0, 301.0]));
+        assert!(!in_range([f32::NAN, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,24 @@
+//! Hardware independent parts of the robot: inverse kinematics, servo motion, leg control,
+//! command scheduling, telemetry, settings, sessions, the HTTP API bodies and the UDP
+//! protocol. Shared by the firmware and the host tools, `cargo test` in this directory runs on
+//! the host.
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(any(test, feature = "test-support"))]
+extern crate alloc;
+
+#[macro_use]
+mod fmt;
+
+pub mod api;
+pub mod auth;
+pub mod command;
+pub mod control;
+pub mod ik;
+pub mod legs;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod protocol;
+pub mod servo;
+pub mod settings;
+pub mod telemetry;
@@ -0,0 +1,32 @@
+//! PWM output for tests, shared by the unit tests and the golden trajectories in `tests/`
+
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::convert::Infallible;
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+/// Keeps the last duty cycle written, 16 bit like the RP2350 PWM slices. Clones share the
+/// output, so a test can keep one to read what the servo wrote.
+#[derive(Clone, Default)]
+pub struct MockPwm(Rc<Cell<u16>>);
+
+impl MockPwm {
+    pub fn duty(&self) -> u16 {
+        self.0.get()
+    }
+}
+
+impl ErrorType for MockPwm {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for MockPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        self.0.set(duty);
+        Ok(())
+    }
+}
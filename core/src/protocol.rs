@@ -0,0 +1,241 @@
+//! Binary teleoperation protocol spoken on [PORT] over UDP.
+//!
+//! Every packet starts with an 8 byte header: magic, version, message kind, a reserved byte
+//! and the little endian sequence number. Vectors are three little endian `f32` in mm or mm/s.
+//! The host tools use it through this crate as well.
+
+pub const PORT: u16 = 4210;
+pub const MAGIC: u8 = 0xD0;
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 8;
+pub const MAX_PACKET_SIZE: usize = HEADER_SIZE + 16;
+
+const KIND_POSE: u8 = 1;
+const KIND_VELOCITY: u8 = 2;
+const KIND_STOP: u8 = 3;
+const KIND_HEARTBEAT: u8 = 4;
+const KIND_STATE: u8 = 0x80;
+
+/// Reply sent by the robot to the last client, `state` is the index of the motion state:
+/// idle, moving, holding, relaxed
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StateReply {
+    pub state: u8,
+    pub estop: bool,
+    pub queued: u16,
+    pub pose: [f32; 3],
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Message {
+    Pose([f32; 3]),
+    Velocity([f32; 3]),
+    Stop,
+    Heartbeat,
+    State(StateReply),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Packet {
+    pub seq: u32,
+    pub message: Message,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+}
+
+fn put_vec(buf: &mut [u8], v: [f32; 3]) {
+    for (i, value) in v.into_iter().enumerate() {
+        buf[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn get_vec(buf: &[u8]) -> [f32; 3] {
+    core::array::from_fn(|i| f32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
+impl Packet {
+    pub fn new(seq: u32, message: Message) -> Self {
+        Self { seq, message }
+    }
+
+    /// Writes the packet into `buf` and returns its length, `None` if `buf` is too small
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let (kind, payload) = match self.message {
+            Message::Pose(_) => (KIND_POSE, 12),
+            Message::Velocity(_) => (KIND_VELOCITY, 12),
+            Message::Stop => (KIND_STOP, 0),
+            Message::Heartbeat => (KIND_HEARTBEAT, 0),
+            Message::State(_) => (KIND_STATE, 16),
+        };
+        let len = HEADER_SIZE + payload;
+        if buf.len() < len {
+            return None;
+        }
+
+        buf[0] = MAGIC;
+        buf[1] = VERSION;
+        buf[2] = kind;
+        buf[3] = 0;
+        buf[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        let body = &mut buf[HEADER_SIZE..len];
+        match self.message {
+            Message::Pose(v) | Message::Velocity(v) => put_vec(body, v),
+            Message::Stop | Message::Heartbeat => {}
+            Message::State(state) => {
+                body[0] = state.state;
+                body[1] = state.estop as u8;
+                body[2..4].copy_from_slice(&state.queued.to_le_bytes());
+                put_vec(&mut body[4..], state.pose);
+            }
+        }
+        Some(len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Packet, DecodeError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(DecodeError::TooShort);
+        }
+        if buf[0] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if buf[1] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(buf[1]));
+        }
+        let seq = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let body = &buf[HEADER_SIZE..];
+        let payload = match buf[2] {
+            KIND_POSE | KIND_VELOCITY => 12,
+            KIND_STATE => 16,
+            KIND_STOP | KIND_HEARTBEAT => 0,
+            kind => return Err(DecodeError::UnknownKind(kind)),
+        };
+        if body.len() < payload {
+            return Err(DecodeError::TooShort);
+        }
+
+        let message = match buf[2] {
+            KIND_POSE => Message::Pose(get_vec(body)),
+            KIND_VELOCITY => Message::Velocity(get_vec(body)),
+            KIND_STOP => Message::Stop,
+            KIND_HEARTBEAT => Message::Heartbeat,
+            _ => Message::State(StateReply {
+                state: body[0],
+                estop: body[1] != 0,
+                queued: u16::from_le_bytes([body[2], body[3]]),
+                pose: get_vec(&body[4..]),
+            }),
+        };
+        Ok(Packet { seq, message })
+    }
+}
+
+/// Drops packets that are older than the newest one seen, handling sequence wrap-around
+pub struct SequenceFilter {
+    last: Option<u32>,
+}
+
+impl SequenceFilter {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    pub fn accept(&mut self, seq: u32) -> bool {
+        let newer = match self.last {
+            None => true,
+            Some(last) => (seq.wrapping_sub(last) as i32) > 0,
+        };
+        if newer {
+            self.last = Some(seq);
+        }
+        newer
+    }
+
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+impl Default for SequenceFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(message: Message) {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let packet = Packet::new(0xDEAD_BEEF, message);
+        let len = packet.encode(&mut buf).unwrap();
+        assert_eq!(Packet::decode(&buf[..len]), Ok(packet));
+    }
+
+    #[test]
+    fn messages_roundtrip() {
+        roundtrip(Message::Pose([1.5, 160.0, -20.25]));
+        roundtrip(Message::Velocity([0.0, -10.0, 3.0]));
+        roundtrip(Message::Stop);
+        roundtrip(Message::Heartbeat);
+        roundtrip(Message::State(StateReply {
+            state: 2,
+            estop: true,
+            queued: 3,
+            pose: [0.0, 160.0, 0.0],
+        }));
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let len = Packet::new(1, Message::Pose([0.0; 3]))
+            .encode(&mut buf)
+            .unwrap();
+
+        assert_eq!(Packet::decode(&buf[..4]), Err(DecodeError::TooShort));
+        assert_eq!(Packet::decode(&buf[..len - 1]), Err(DecodeError::TooShort));
+        assert!(
+            Packet::new(1, Message::Pose([0.0; 3]))
+                .encode(&mut buf[..10])
+                .is_none()
+        );
+
+        let mut bad = buf;
+        bad[0] = 0;
+        assert_eq!(Packet::decode(&bad[..len]), Err(DecodeError::BadMagic));
+        let mut bad = buf;
+        bad[1] = 9;
+        assert_eq!(
+            Packet::decode(&bad[..len]),
+            Err(DecodeError::UnsupportedVersion(9))
+        );
+        let mut bad = buf;
+        bad[2] = 42;
+        assert_eq!(
+            Packet::decode(&bad[..len]),
+            Err(DecodeError::UnknownKind(42))
+        );
+    }
+
+    #[test]
+    fn drops_stale_sequence_numbers() {
+        let mut filter = SequenceFilter::new();
+        assert!(filter.accept(10));
+        assert!(!filter.accept(10));
+        assert!(!filter.accept(9));
+        assert!(filter.accept(12));
+
+        filter.reset();
+        assert!(filter.accept(u32::MAX - 1));
+        assert!(filter.accept(u32::MAX));
+        assert!(filter.accept(0));
+        assert!(!filter.accept(u32::MAX));
+    }
+}
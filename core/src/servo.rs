@@ -0,0 +1,374 @@
+//! Servo calibration, angle mapping and eased motion, driving any [SetDutyCycle] output
+
+use core::f32::consts::PI;
+use embedded_hal::pwm::SetDutyCycle;
+use libm::cosf;
+use serde::Serialize;
+
+pub const SERVO_COUNT: usize = 12;
+pub const LEG_COUNT: usize = SERVO_COUNT / 3;
+/// Ticks a motion takes from start to target
+pub const MOTION_TICKS: u32 = 150;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionState {
+    /// Standing still in the home position
+    Idle,
+    Moving,
+    /// Standing still and powered somewhere other than home
+    Holding,
+    /// PWM switched off
+    Relaxed,
+}
+
+impl MotionState {
+    /// Combines the joint states into the state of the whole robot
+    pub fn of(joints: &[MotionState]) -> MotionState {
+        if joints.contains(&MotionState::Moving) {
+            MotionState::Moving
+        } else if joints.iter().all(|j| *j == MotionState::Relaxed) {
+            MotionState::Relaxed
+        } else if joints.iter().all(|j| *j == MotionState::Idle) {
+            MotionState::Idle
+        } else {
+            MotionState::Holding
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ServoConfig {
+    min_angle: f32,
+    max_angle: f32,
+    home: f32,
+
+    /// clamp output
+    calibrated: bool,
+
+    max_rotation: u16,
+    /// invert angle
+    inverted: bool,
+    /// offset added to the max_rotation after inversion
+    offset: u16,
+}
+
+impl ServoConfig {
+    pub fn new(
+        min: f32,
+        home: f32,
+        max: f32,
+        max_rotation: u16,
+        offset: u16,
+        inverted: bool,
+        calibrated: bool,
+    ) -> Self {
+        ServoConfig {
+            min_angle: min,
+            max_angle: max,
+            max_rotation,
+            offset,
+            inverted,
+            calibrated,
+            home,
+        }
+    }
+
+    pub fn hip(min: f32, home: f32, max: f32) -> Self {
+        ServoConfig::new(min, home, max, 90, 45, false, true)
+    }
+
+    pub fn thigh(min: f32, home: f32, max: f32) -> Self {
+        ServoConfig::new(min, home, max, 180 - 15, 90, false, true)
+    }
+
+    pub fn foot(min: f32, home: f32, max: f32) -> Self {
+        ServoConfig::new(min, home, max, 180 - 15, 0, true, true)
+    }
+
+    /// Calibration of the assembled dog, hip, thigh and foot of every leg
+    pub fn calibration() -> [ServoConfig; SERVO_COUNT] {
+        [
+            ServoConfig::hip(0.05, 0.068, 0.1),
+            ServoConfig::thigh(0.073, 0.088, 0.115),
+            ServoConfig::foot(0.028, 0.0678, 0.118),
+            ServoConfig::hip(0.06, 0.088, 0.105),
+            ServoConfig::thigh(0.056, 0.085, 0.102),
+            ServoConfig::foot(0.0275, 0.077, 0.119),
+            ServoConfig::hip(0.04, 0.060, 0.093),
+            ServoConfig::thigh(0.07, 0.1, 0.12),
+            ServoConfig::foot(0.035, 0.086, 0.119),
+            ServoConfig::hip(0.055, 0.085, 0.1),
+            ServoConfig::thigh(0.07, 0.1, 0.12),
+            ServoConfig::foot(0.03, 0.08, 0.125),
+        ]
+    }
+
+    /// Duty cycle fraction for a joint angle in degree, before clamping
+    pub fn percentage(&self, degree: f32) -> f32 {
+        let mut degree = degree;
+        if self.inverted {
+            degree = self.max_rotation as f32 - degree;
+        }
+        if self.offset != 0 {
+            degree += self.offset as f32;
+        }
+        if degree.is_sign_negative() {
+            error!("Degree is negative, assuming 0.0: {}", degree);
+            degree = 0.0;
+        }
+
+        let max_deg = self.max_rotation as f32;
+        let delta = self.max_angle - self.min_angle;
+        let degree_percent = degree / max_deg;
+        self.min_angle + (degree_percent * delta)
+    }
+
+    /// Limits a duty cycle fraction to 0..=1, and to the calibrated range if there is one
+    pub fn clamp(&self, percentage: f32) -> f32 {
+        let mut percentage = percentage;
+        if !(0.0..=1.0).contains(&percentage) {
+            error!("Percentage must be between 0 and 1, is: {}", percentage);
+            percentage = percentage.clamp(0.0, 1.0);
+        }
+        if self.calibrated && (percentage > self.max_angle || percentage < self.min_angle) {
+            let clamped = percentage.clamp(self.min_angle, self.max_angle);
+            error!(
+                "Percentage {} out of range, Clamped: {}",
+                percentage, clamped
+            );
+            percentage = clamped;
+        }
+        percentage
+    }
+}
+
+/// Sine ease-in-out from `start` to `end`, `x` runs from 0 to 1
+pub fn ease_sine(x: f32, start: f32, end: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    let sine = -(cosf(PI * x) - 1.0) / 2.0;
+    let diff = end - start;
+    start + (diff * sine)
+}
+
+pub struct Servo<P> {
+    pwm: P,
+    config: ServoConfig,
+
+    step: f32,
+    prev: u16,
+    duty: u16,
+    target: u16,
+    /// PWM output switched off
+    relaxed: bool,
+    /// Last angle requested through [Servo::rotate]
+    angle: Option<f32>,
+}
+
+impl<P: SetDutyCycle> Servo<P> {
+    pub fn new(mut pwm: P, config: ServoConfig) -> Self {
+        let home = (config.home * pwm.max_duty_cycle() as f32) as u16;
+        pwm.set_duty_cycle(home).expect("set_duty_cycle failed");
+        Self {
+            pwm,
+            config,
+            step: 1.0,
+            prev: home,
+            duty: home,
+            target: home,
+            relaxed: false,
+            angle: None,
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.write(self.config.home);
+    }
+
+    pub fn rotate(&mut self, degree: f32) {
+        if !degree.is_finite() {
+            debug!("NaN Requested! Homing...");
+            self.home();
+            return;
+        }
+        self.write(self.config.percentage(degree));
+        self.angle = Some(degree);
+    }
+
+    pub fn write(&mut self, percentage: f32) {
+        let percentage = self.config.clamp(percentage);
+        let max = self.pwm.max_duty_cycle();
+        let val = (percentage * max as f32) as u16;
+        self.target = val;
+        self.prev = self.duty;
+        self.step = 0.0;
+        self.relaxed = false;
+        self.angle = None;
+        debug!("Set next duty cycle to={}", val);
+        self.tick();
+    }
+
+    /// Stops the running motion at the current duty cycle
+    pub fn hold(&mut self) {
+        self.target = self.duty;
+        self.prev = self.duty;
+        self.step = 1.0;
+    }
+
+    /// Switches the PWM output off, so the servo no longer holds its position
+    pub fn relax(&mut self) {
+        self.hold();
+        self.relaxed = true;
+        self.angle = None;
+        self.pwm.set_duty_cycle(0).unwrap();
+    }
+
+    pub fn angle(&self) -> Option<f32> {
+        self.angle
+    }
+
+    /// Duty cycle written to the output, 0 while relaxed
+    pub fn duty(&self) -> u16 {
+        if self.relaxed { 0 } else { self.duty }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.step >= 1.0
+    }
+
+    pub fn state(&self) -> MotionState {
+        let home = (self.config.home * self.pwm.max_duty_cycle() as f32) as u16;
+        if self.relaxed {
+            MotionState::Relaxed
+        } else if !self.is_idle() {
+            MotionState::Moving
+        } else if self.target == home {
+            MotionState::Idle
+        } else {
+            MotionState::Holding
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.step < 1.0 {
+            let inc = 1.0 / MOTION_TICKS as f32;
+            self.step += inc;
+        } else {
+            return;
+        }
+
+        self.duty = ease_sine(self.step, self.prev as f32, self.target as f32) as u16;
+
+        self.pwm.set_duty_cycle(self.duty).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockPwm;
+
+    fn thigh() -> Servo<MockPwm> {
+        Servo::new(MockPwm::default(), ServoConfig::thigh(0.07, 0.1, 0.12))
+    }
+
+    #[test]
+    fn easing_starts_and_ends_at_the_bounds() {
+        assert_eq!(ease_sine(0.0, 100.0, 200.0), 100.0);
+        assert_eq!(ease_sine(1.0, 100.0, 200.0), 200.0);
+        assert!((ease_sine(0.5, 100.0, 200.0) - 150.0).abs() < 1e-3);
+        assert_eq!(ease_sine(2.0, 100.0, 200.0), 200.0);
+    }
+
+    #[test]
+    fn inverts_and_offsets_angles() {
+        let foot = ServoConfig::foot(0.03, 0.08, 0.125);
+        assert!((foot.percentage(165.0) - 0.03).abs() < 1e-6);
+        assert!((foot.percentage(0.0) - 0.125).abs() < 1e-6);
+
+        let hip = ServoConfig::hip(0.05, 0.068, 0.1);
+        assert!((hip.percentage(-45.0) - 0.05).abs() < 1e-6);
+        assert!((hip.percentage(45.0) - 0.1).abs() < 1e-6);
+        // below the range after the offset, treated as 0°
+        assert_eq!(hip.percentage(-90.0), hip.percentage(-45.0));
+    }
+
+    #[test]
+    fn clamps_to_the_calibrated_range() {
+        let thigh = ServoConfig::thigh(0.07, 0.1, 0.12);
+        assert_eq!(thigh.clamp(0.5), 0.12);
+        assert_eq!(thigh.clamp(0.01), 0.07);
+        assert_eq!(thigh.clamp(0.1), 0.1);
+
+        let uncalibrated = ServoConfig::new(0.05, 0.075, 0.1, 180, 0, false, false);
+        assert_eq!(uncalibrated.clamp(0.5), 0.5);
+        assert_eq!(uncalibrated.clamp(1.5), 1.0);
+        assert_eq!(uncalibrated.clamp(-0.5), 0.0);
+    }
+
+    #[test]
+    fn motion_eases_to_the_target() {
+        let mut servo = thigh();
+        let home = servo.duty();
+        assert_eq!(servo.state(), MotionState::Idle);
+
+        servo.write(0.12);
+        let target = (0.12 * u16::MAX as f32) as u16;
+        assert_eq!(servo.state(), MotionState::Moving);
+        let mut last = servo.duty();
+        assert!(last >= home && last < target);
+        while !servo.is_idle() {
+            servo.tick();
+            assert!(servo.duty() >= last);
+            last = servo.duty();
+            assert_eq!(servo.pwm.duty(), last);
+        }
+        assert!(target - servo.duty() <= 1);
+        assert_eq!(servo.state(), MotionState::Holding);
+    }
+
+    #[test]
+    fn hold_and_relax_stop_the_motion() {
+        let mut servo = thigh();
+        servo.write(0.12);
+        servo.tick();
+        servo.hold();
+        let held = servo.duty();
+        servo.tick();
+        assert_eq!(servo.duty(), held);
+        assert!(servo.is_idle());
+
+        servo.rotate(90.0);
+        assert_eq!(servo.angle(), Some(90.0));
+        servo.relax();
+        assert_eq!(servo.state(), MotionState::Relaxed);
+        assert_eq!(servo.duty(), 0);
+        assert_eq!(servo.pwm.duty(), 0);
+        assert_eq!(servo.angle(), None);
+    }
+
+    #[test]
+    fn non_finite_angles_home() {
+        let mut servo = thigh();
+        servo.write(0.12);
+        while !servo.is_idle() {
+            servo.tick();
+        }
+        servo.rotate(f32::NAN);
+        assert_eq!(servo.angle(), None);
+        while !servo.is_idle() {
+            servo.tick();
+        }
+        assert_eq!(servo.state(), MotionState::Idle);
+    }
+
+    #[test]
+    fn robot_state_combines_joints() {
+        use MotionState::*;
+        assert_eq!(MotionState::of(&[Idle, Moving, Relaxed]), Moving);
+        assert_eq!(MotionState::of(&[Relaxed, Relaxed]), Relaxed);
+        assert_eq!(MotionState::of(&[Idle, Idle]), Idle);
+        assert_eq!(MotionState::of(&[Idle, Holding]), Holding);
+        assert_eq!(MotionState::of(&[Idle, Relaxed]), Holding);
+    }
+}
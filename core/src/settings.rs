@@ -0,0 +1,226 @@
+//! Settings of the robot and their validation. The firmware stores them in the last flash
+//! sector, the simulator keeps them in memory.
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+/// Default hostname, used for DHCP and mDNS unless one is stored in the settings
+pub const HOSTNAME: &str = "robodog";
+pub const MAX_HOSTNAME_LEN: usize = 32;
+/// Address of the robot in access point mode, unless changed in the settings
+pub const DEFAULT_AP_ADDRESS: [u8; 4] = [169, 254, 1, 1];
+pub const DEFAULT_AP_PREFIX_LEN: u8 = 16;
+/// Upper bound for [AccessPoint::pool_size], the DHCP server has a lease slot for each
+pub const MAX_LEASES: u8 = 16;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Station {
+    pub ssid: String<32>,
+    pub password: String<64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessPoint {
+    pub ssid: String<32>,
+    /// WPA2 passphrase, 8 to 63 characters
+    pub password: String<64>,
+    pub channel: u8,
+    /// Static address of the robot, the DHCP pool is in the same subnet
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+    /// First address leased to clients, the following `pool_size - 1` are leased as well
+    pub pool_start: [u8; 4],
+    pub pool_size: u8,
+    pub lease_time_s: u32,
+}
+
+impl Default for AccessPoint {
+    fn default() -> Self {
+        AccessPoint {
+            ssid: String::try_from("robodog_ap").unwrap(),
+            password: String::try_from("robodogg").unwrap(),
+            channel: 5,
+            address: DEFAULT_AP_ADDRESS,
+            prefix_len: DEFAULT_AP_PREFIX_LEN,
+            pool_start: [169, 254, 1, 100],
+            pool_size: MAX_LEASES,
+            lease_time_s: 2 * 60 * 60,
+        }
+    }
+}
+
+impl AccessPoint {
+    fn validate(&self) -> Result<(), SettingsError> {
+        if self.ssid.is_empty() {
+            return Err(SettingsError::Invalid("ssid must not be empty"));
+        }
+        if !(8..=63).contains(&self.password.len()) {
+            return Err(SettingsError::Invalid(
+                "password must have 8 to 63 characters",
+            ));
+        }
+        if !(1..=13).contains(&self.channel) {
+            return Err(SettingsError::Invalid("channel must be between 1 and 13"));
+        }
+        // the DHCP pool needs the last octet to itself
+        if !(8..=24).contains(&self.prefix_len) {
+            return Err(SettingsError::Invalid(
+                "prefix length must be between 8 and 24",
+            ));
+        }
+        let [a, _, _, d] = self.address;
+        if a == 0 || a >= 224 || d == 0 || d == 255 {
+            return Err(SettingsError::Invalid("invalid address"));
+        }
+        self.validate_pool()
+    }
+
+    /// The pool has to lie in the subnet of the address, without its network and broadcast
+    /// address and without the address of the robot
+    fn validate_pool(&self) -> Result<(), SettingsError> {
+        if !(1..=MAX_LEASES).contains(&self.pool_size) {
+            return Err(SettingsError::Invalid("pool size must be between 1 and 16"));
+        }
+        if self.lease_time_s < 60 {
+            return Err(SettingsError::Invalid(
+                "lease time must be at least 60 seconds",
+            ));
+        }
+        let mask = u32::MAX << (32 - self.prefix_len);
+        let network = u32::from_be_bytes(self.address) & mask;
+        let broadcast = network | !mask;
+        let first = u32::from_be_bytes(self.pool_start);
+        let last = first.saturating_add(self.pool_size as u32 - 1);
+        if first <= network || last >= broadcast || first & mask != network {
+            return Err(SettingsError::Invalid("pool must be inside the subnet"));
+        }
+        if (first..=last).contains(&u32::from_be_bytes(self.address)) {
+            return Err(SettingsError::Invalid("pool must not contain the address"));
+        }
+        Ok(())
+    }
+}
+
+/// Power saving of the Wi-Fi chip, trades latency for current draw
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerManagement {
+    None,
+    #[default]
+    PowerSave,
+    Performance,
+    Aggressive,
+    SuperSave,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Network to join on boot, the access point is only opened if this is unset or fails
+    pub station: Option<Station>,
+    /// Name announced over DHCP and mDNS, [HOSTNAME] if unset
+    pub hostname: Option<String<MAX_HOSTNAME_LEN>>,
+    pub access_point: AccessPoint,
+    pub power_management: PowerManagement,
+    /// Password for control routes, the access point password if unset
+    pub password: Option<String<64>>,
+}
+
+impl Settings {
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_deref().unwrap_or(HOSTNAME)
+    }
+
+    pub fn password(&self) -> &str {
+        self.password
+            .as_deref()
+            .unwrap_or(&self.access_point.password)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.password.as_ref().is_some_and(|p| p.len() < 8) {
+            return Err(SettingsError::Invalid(
+                "password must have at least 8 characters",
+            ));
+        }
+        if self.station.as_ref().is_some_and(|s| s.ssid.is_empty()) {
+            return Err(SettingsError::Invalid("ssid must not be empty"));
+        }
+        if !valid_hostname(self.hostname()) {
+            return Err(SettingsError::Invalid("invalid hostname"));
+        }
+        self.access_point.validate()
+    }
+}
+
+/// Hostnames are a single DNS label
+fn valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+        && hostname
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SettingsError {
+    Invalid(&'static str),
+    TooLarge,
+    Flash,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(Settings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_hostnames() {
+        let mut settings = Settings::default();
+        for hostname in ["", "-robodog", "robo_dog", "robodog.local"] {
+            settings.hostname = Some(String::try_from(hostname).unwrap());
+            assert_eq!(
+                settings.validate(),
+                Err(SettingsError::Invalid("invalid hostname"))
+            );
+        }
+    }
+
+    #[test]
+    fn pool_is_inside_the_subnet() {
+        let mut settings = Settings::default();
+        let ap = &mut settings.access_point;
+        ap.prefix_len = 24;
+        ap.pool_start = [169, 254, 1, 250];
+        ap.pool_size = 6;
+        assert_eq!(
+            settings.validate(),
+            Err(SettingsError::Invalid("pool must be inside the subnet"))
+        );
+
+        let ap = &mut settings.access_point;
+        ap.pool_size = 5;
+        assert_eq!(settings.validate(), Ok(()));
+
+        let ap = &mut settings.access_point;
+        ap.pool_start = [169, 254, 2, 100];
+        assert_eq!(
+            settings.validate(),
+            Err(SettingsError::Invalid("pool must be inside the subnet"))
+        );
+
+        let ap = &mut settings.access_point;
+        ap.pool_start = [169, 254, 1, 1];
+        assert_eq!(
+            settings.validate(),
+            Err(SettingsError::Invalid("pool must not contain the address"))
+        );
+    }
+}
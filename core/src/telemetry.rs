@@ -0,0 +1,83 @@
+//! Snapshot of the legs and the control loop timing for the telemetry stream
+
+use crate::legs::Legs;
+use crate::servo::{LEG_COUNT, SERVO_COUNT};
+use embedded_hal::pwm::SetDutyCycle;
+use serde::Serialize;
+
+/// Length of the window the worst control loop period is taken over
+const LOOP_WINDOW_US: u64 = 1_000_000;
+
+#[derive(Copy, Clone, Serialize)]
+pub struct Telemetry {
+    /// Last commanded angle per servo in degree, `None` after a raw duty cycle write
+    pub angles: [Option<f32>; SERVO_COUNT],
+    /// Duty cycle currently written to each PWM output
+    pub duty: [u16; SERVO_COUNT],
+    /// Foot target per leg in mm, `None` if the leg was not positioned through IK
+    pub feet: [Option<[f32; 3]>; LEG_COUNT],
+    pub ik_failures: u32,
+    /// Period of the last control loop tick
+    pub loop_us: u32,
+    /// Longest control loop period within the last second
+    pub loop_max_us: u32,
+}
+
+pub struct Recorder {
+    telemetry: Telemetry,
+    /// Uptime in µs of the last tick
+    last_tick_us: Option<u64>,
+    window_start_us: u64,
+    window_max_us: u32,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            telemetry: Telemetry {
+                angles: [None; SERVO_COUNT],
+                duty: [0; SERVO_COUNT],
+                feet: [None; LEG_COUNT],
+                ik_failures: 0,
+                loop_us: 0,
+                loop_max_us: 0,
+            },
+            last_tick_us: None,
+            window_start_us: 0,
+            window_max_us: 0,
+        }
+    }
+
+    pub fn snapshot(&self) -> Telemetry {
+        self.telemetry
+    }
+
+    pub fn record_legs<P: SetDutyCycle>(&mut self, legs: &Legs<P>) {
+        self.telemetry.angles = legs.angles();
+        self.telemetry.duty = legs.duty();
+        self.telemetry.feet = legs.feet();
+        self.telemetry.ik_failures = legs.ik_failures();
+    }
+
+    /// Called on every control loop tick to measure its period
+    pub fn record_tick(&mut self, now_us: u64) {
+        if let Some(last) = self.last_tick_us {
+            let period = now_us.saturating_sub(last) as u32;
+            self.telemetry.loop_us = period;
+            self.window_max_us = self.window_max_us.max(period);
+        }
+        self.last_tick_us = Some(now_us);
+
+        if now_us.saturating_sub(self.window_start_us) >= LOOP_WINDOW_US {
+            self.telemetry.loop_max_us = self.window_max_us;
+            self.window_max_us = 0;
+            self.window_start_us = now_us;
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Replays scripted commands through the control loop on a virtual clock and compares the duty
+//! cycle written to every PWM output on every tick with the CSV files in `tests/golden/`.
+//!
+//! After an intended change to the motion, the files are rewritten with
+//! `UPDATE_GOLDEN=1 cargo test --test trajectory`; review the diff before committing them.
+
+use robodog_core::command::ServoTask;
+use robodog_core::control::{ControlLoop, Scheduler, Step, TICK_MS};
+use robodog_core::legs::Legs;
+use robodog_core::mock::MockPwm;
+use robodog_core::servo::{SERVO_COUNT, Servo, ServoConfig};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::{env, fs};
+
+/// Submits the tasks of `script` at their uptime in ms and returns one CSV row per tick until
+/// `ticks` ticks have passed. The loop waits like `servo_task`: a submission wakes it without
+/// a tick and the next tick follows a full [TICK_MS] after that.
+fn record(script: &[(u64, ServoTask)], ticks: u32) -> String {
+    let outputs: [MockPwm; SERVO_COUNT] = Default::default();
+    let configs = ServoConfig::calibration();
+    let mut control = ControlLoop::new(Legs::new(core::array::from_fn(|i| {
+        Servo::new(outputs[i].clone(), configs[i])
+    })));
+    let mut scheduler = Scheduler::new();
+    let mut script = script.iter().copied().peekable();
+    let mut now_ms = 0;
+
+    let mut csv = String::from("tick,ms");
+    for i in 0..SERVO_COUNT {
+        write!(csv, ",servo_{i}").unwrap();
+    }
+    csv.push('\n');
+
+    let mut tick = 0;
+    while tick < ticks {
+        while let Some((_, task)) = script.next_if(|(at, _)| *at <= now_ms) {
+            scheduler.submit(task).unwrap();
+        }
+        while control.step(&mut scheduler, now_ms, &mut |_| {}) == Step::Busy {}
+
+        let deadline = now_ms + TICK_MS;
+        if let Some(&(at, _)) = script.peek()
+            && at < deadline
+        {
+            now_ms = at;
+            continue;
+        }
+        now_ms = deadline;
+        control.tick();
+
+        write!(csv, "{tick},{now_ms}").unwrap();
+        for output in &outputs {
+            write!(csv, ",{}", output.duty()).unwrap();
+        }
+        csv.push('\n');
+        tick += 1;
+    }
+    assert!(script.next().is_none(), "script runs past the recording");
+    csv
+}
+
+fn check(name: &str, script: &[(u64, ServoTask)], ticks: u32) {
+    let actual = record(script, ticks);
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.csv"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|err| panic!("{}: {err}, run with UPDATE_GOLDEN=1", golden.display()));
+    if actual != expected {
+        let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.csv"));
+        fs::write(&out, &actual).unwrap();
+        let (expected_row, actual_row) = expected
+            .lines()
+            .zip(actual.lines())
+            .find(|(e, a)| e != a)
+            .unwrap_or(("<end>", "<end>"));
+        panic!(
+            "{name} differs from {}, recorded in {}\nexpected: {expected_row}\nactual:   {actual_row}",
+            golden.display(),
+            out.display()
+        );
+    }
+}
+
+#[test]
+fn pose_sequence() {
+    // queued motions start one after the other, each eased over MOTION_TICKS
+    check(
+        "pose_sequence",
+        &[
+            (0, ServoTask::MOVE(0.0, 150.0, 0.0)),
+            (50, ServoTask::MOVE(20.0, 140.0, 15.0)),
+            (100, ServoTask::LEG(1, -20.0, 160.0, 0.0)),
+            // between two ticks, the tick after it comes a full period later
+            (152, ServoTask::HOME),
+        ],
+        640,
+    );
+}
+
+#[test]
+fn stop_and_relax() {
+    check(
+        "stop_and_relax",
+        &[
+            (0, ServoTask::MOVE(0.0, 150.0, 0.0)),
+            // dropped by the stop before it starts
+            (200, ServoTask::MOVE(0.0, 170.0, 0.0)),
+            (302, ServoTask::STOP),
+            (
+                400,
+                ServoTask::JOINTS([
+                    Some(45.0),
+                    None,
+                    Some(90.0),
+                    Some(45.0),
+                    None,
+                    Some(90.0),
+                    None,
+                    Some(60.0),
+                    None,
+                    None,
+                    Some(60.0),
+                    None,
+                ]),
+            ),
+            (1200, ServoTask::RELAX),
+            // powers up again from the duty cycle held before relaxing
+            (1300, ServoTask::HOME),
+        ],
+        440,
+    );
+}
+
+#[test]
+fn calibration_limits() {
+    check(
+        "calibration_limits",
+        &[
+            // beyond the calibrated range on both ends, clamped
+            (0, ServoTask::CALIBRATION(0, 0.2)),
+            (0, ServoTask::CALIBRATION(2, 0.0)),
+            // no such servo, nothing moves
+            (0, ServoTask::CALIBRATION(12, 0.1)),
+            (0, ServoTask::JOINTS([Some(0.0); SERVO_COUNT])),
+            (0, ServoTask::JOINTS([Some(180.0); SERVO_COUNT])),
+            // not reachable, homes every leg
+            (0, ServoTask::MOVE(0.0, 250.0, 0.0)),
+        ],
+        800,
+    );
+}
@@ -4,8 +4,21 @@ MEMORY {
      *
      * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 3M
-    STATIC : ORIGIN = 0x10300000, LENGTH = 1M
+    /*
+     * The bootloader in `bootloader/` lives in the first 24K and swaps
+     * DFU into FLASH when an update was uploaded, see src/ota.rs.
+     */
+    BOOTLOADER : ORIGIN = 0x10000000, LENGTH = 24K
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10007000, LENGTH = 1520K
+    /* one sector larger than FLASH, needed for swapping */
+    DFU : ORIGIN = 0x10183000, LENGTH = 1524K
+    /* files flashed by build.rs, see flash_files */
+    STATIC : ORIGIN = 0x10300000, LENGTH = 512K
+    /* web assets uploaded over HTTP, see src/assets.rs */
+    ASSETS : ORIGIN = 0x10380000, LENGTH = 512K - 4K
+    /* persistent settings, see src/settings.rs */
+    SETTINGS : ORIGIN = 0x103FF000, LENGTH = 4K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -72,5 +85,11 @@ SECTIONS {
 
 } INSERT AFTER .uninit;
 
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
+
 PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
 PROVIDE(end_to_start = __start_block_addr - __end_block_addr);
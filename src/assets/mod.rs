@@ -0,0 +1,328 @@
+pub mod table;
+
+use crate::crc32::Crc32;
+use crate::peripheral::flash::{self, ERASE_SIZE, FLASH_BASE};
+use core::cell::Cell;
+use defmt::{Format, error, info, warn};
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::once_lock::OnceLock;
+use heapless::String;
+use table::{Entry, HEADER_SIZE, Header, TableError};
+
+/// `STATIC` in `memory.x`, holding the files of `flash_files`
+#[cfg(not(feature = "embed-assets"))]
+const STATIC_START: u32 = 0x1030_0000;
+#[cfg(not(feature = "embed-assets"))]
+const STATIC_SIZE: u32 = 512 * 1024;
+
+/// `ASSETS` in `memory.x`, holding the bundle uploaded over HTTP
+const ASSETS_START: u32 = 0x1038_0000;
+const ASSETS_SIZE: u32 = 512 * 1024 - 4 * 1024;
+const ASSETS_OFFSET: u32 = ASSETS_START - FLASH_BASE;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Format)]
+pub enum AssetError {
+    /// Another upload is running
+    Busy,
+    /// Files of the uploaded bundle are being sent, it can't be erased yet
+    InUse,
+    /// Bad header or entry table
+    Invalid,
+    TooLarge,
+    /// Received data does not match the checksum in the header
+    Checksum,
+    Flash,
+}
+
+impl Format for TableError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            TableError::Missing => defmt::write!(f, "Missing"),
+            TableError::Version(version) => defmt::write!(f, "Version({})", version),
+            TableError::Corrupt => defmt::write!(f, "Corrupt"),
+        }
+    }
+}
+
+/// A file of an asset bundle
+pub struct File {
+    pub data: &'static [u8],
+    pub content_type: &'static str,
+    /// CRC-32 of `data`
+    pub crc: u32,
+    /// `data` is gzip compressed
+    pub gzip: bool,
+    /// Keeps the uploaded bundle from being erased until the file is dropped
+    _reading: Option<Reading>,
+}
+
+impl File {
+    fn new(bundle: &'static [u8], entry: Entry<'static>) -> Self {
+        File {
+            data: entry.data(bundle),
+            content_type: entry.content_type,
+            crc: entry.crc,
+            gzip: entry.gzip,
+            _reading: None,
+        }
+    }
+}
+
+fn find(bundle: &'static [u8], path: &str, gzip: bool) -> Option<File> {
+    Some(File::new(bundle, table::find(bundle, path, gzip)?))
+}
+
+#[cfg(feature = "embed-assets")]
+fn static_region() -> &'static [u8] {
+    include_bytes!(concat!(env!("OUT_DIR"), "/static.bin"))
+}
+
+#[cfg(not(feature = "embed-assets"))]
+fn static_region() -> &'static [u8] {
+    // SAFETY: the region is mapped flash, written by `build.rs` and never by the firmware
+    unsafe { core::slice::from_raw_parts(STATIC_START as *const u8, STATIC_SIZE as usize) }
+}
+
+/// The bundle built from `flash_files`, `None` if it is missing or does not match this build
+fn builtin_bundle() -> Option<&'static [u8]> {
+    static BUNDLE: OnceLock<Option<&'static [u8]>> = OnceLock::new();
+    *BUNDLE.get_or_init(|| {
+        let region = static_region();
+        match table::validate(region) {
+            Ok(header) => {
+                info!("{} built-in assets", header.count);
+                Some(region)
+            }
+            Err(err) => {
+                error!("Built-in assets unusable: {}", err);
+                None
+            }
+        }
+    })
+}
+
+/// Looks up a file listed in `flash_files`, like `www/index.html`
+pub fn builtin(path: &str) -> Option<File> {
+    find(builtin_bundle()?, path, false)
+}
+
+/// Directory of the web files in `flash_files`, served from the root
+const WEB_ROOT: &str = "www/";
+
+/// Looks up a file served over HTTP, `path` without leading slash. The uploaded bundle takes
+/// precedence over the files built from `www/`. With `gzip`, the compressed data is preferred.
+pub fn web(path: &str, gzip: bool) -> Option<File> {
+    uploaded(path, gzip).or_else(|| {
+        let mut builtin_path = String::<{ table::MAX_PATH_LEN }>::new();
+        builtin_path.push_str(WEB_ROOT).ok()?;
+        builtin_path.push_str(path).ok()?;
+        find(builtin_bundle()?, &builtin_path, gzip)
+    })
+}
+
+/// Like [`builtin`], for files the firmware can't do without
+pub fn builtin_bytes(path: &str) -> &'static [u8] {
+    match builtin(path) {
+        Some(file) => file.data,
+        None => panic!(
+            "{} missing from the STATIC region, reflash the assets",
+            path
+        ),
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Region {
+    /// The `ASSETS` region holds a complete bundle, cleared while uploading
+    uploaded: bool,
+    /// Files of the bundle that are still being read, the region is only erased without any
+    readers: u16,
+}
+
+static REGION: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Region>> =
+    blocking_mutex::Mutex::new(Cell::new(Region {
+        uploaded: false,
+        readers: 0,
+    }));
+
+fn update_region<R>(f: impl FnOnce(&mut Region) -> R) -> R {
+    REGION.lock(|cell| {
+        let mut region = cell.get();
+        let result = f(&mut region);
+        cell.set(region);
+        result
+    })
+}
+
+/// A read of the uploaded bundle, counted in [Region::readers]
+struct Reading(());
+
+impl Reading {
+    /// `None` if there is no complete bundle to read
+    fn start() -> Option<Self> {
+        update_region(|region| {
+            region.readers += region.uploaded as u16;
+            region.uploaded.then_some(Reading(()))
+        })
+    }
+}
+
+impl Drop for Reading {
+    fn drop(&mut self) {
+        update_region(|region| region.readers -= 1);
+    }
+}
+
+fn assets_region() -> &'static [u8] {
+    // SAFETY: the region is mapped flash only written through `flash`, and only after
+    // clearing `uploaded` while no file is read, so lookups never see a half written bundle
+    unsafe { core::slice::from_raw_parts(ASSETS_START as *const u8, ASSETS_SIZE as usize) }
+}
+
+/// Checks both bundles, before anything is looked up
+pub fn init() {
+    builtin_bundle();
+    match table::validate(assets_region()) {
+        Ok(header) => {
+            info!("{} uploaded assets", header.count);
+            update_region(|region| region.uploaded = true);
+        }
+        Err(TableError::Missing) => {}
+        Err(err) => warn!("Uploaded assets ignored: {}", err),
+    }
+}
+
+/// Looks up a file of the uploaded bundle, `path` without leading slash
+pub fn uploaded(path: &str, gzip: bool) -> Option<File> {
+    let reading = Reading::start()?;
+    let mut file = find(assets_region(), path, gzip)?;
+    file._reading = Some(reading);
+    Some(file)
+}
+
+pub struct Upload {
+    /// Bytes received so far, including the header
+    len: u32,
+    header: Option<Header>,
+    crc: Crc32,
+    /// First sector, held back and written last so the header only becomes valid at the end
+    first: [u8; ERASE_SIZE],
+    buf: [u8; ERASE_SIZE],
+}
+
+static UPLOAD: Mutex<CriticalSectionRawMutex, Upload> = Mutex::new(Upload {
+    len: 0,
+    header: None,
+    crc: Crc32::new(),
+    first: [0; ERASE_SIZE],
+    buf: [0; ERASE_SIZE],
+});
+
+/// Erases the uploaded bundle, the files built into the firmware are served again. Refused
+/// while files of the bundle are still being sent.
+pub fn clear() -> Result<(), AssetError> {
+    update_region(|region| {
+        if region.readers > 0 {
+            return Err(AssetError::InUse);
+        }
+        region.uploaded = false;
+        Ok(())
+    })?;
+    flash::erase(ASSETS_OFFSET, ASSETS_OFFSET + ERASE_SIZE as u32).map_err(|_| AssetError::Flash)
+}
+
+/// Starts replacing the uploaded bundle, one upload at a time
+pub fn begin() -> Result<MutexGuard<'static, CriticalSectionRawMutex, Upload>, AssetError> {
+    let mut upload = UPLOAD.try_lock().map_err(|_| AssetError::Busy)?;
+    clear()?;
+    upload.len = 0;
+    upload.header = None;
+    upload.crc = Crc32::new();
+    info!("Asset upload started");
+    Ok(upload)
+}
+
+impl Upload {
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), AssetError> {
+        while !data.is_empty() {
+            let pos = self.len as usize % ERASE_SIZE;
+            let n = data.len().min(ERASE_SIZE - pos);
+            let chunk = &data[..n];
+            if self.len as usize >= ERASE_SIZE {
+                self.buf[pos..pos + n].copy_from_slice(chunk);
+            } else {
+                self.first[pos..pos + n].copy_from_slice(chunk);
+            }
+
+            // the checksum covers everything after the header
+            let skip = (HEADER_SIZE as u32).saturating_sub(self.len) as usize;
+            self.crc.update(&chunk[skip.min(n)..]);
+            self.len += n as u32;
+            data = &data[n..];
+
+            if self.header.is_none() && self.len as usize >= HEADER_SIZE {
+                let header = Header::parse(&self.first).map_err(|_| AssetError::Invalid)?;
+                if header.size() > ASSETS_SIZE as usize {
+                    return Err(AssetError::TooLarge);
+                }
+                self.header = Some(header);
+            }
+            if self.header.is_some_and(|h| self.len as usize > h.size()) {
+                return Err(AssetError::TooLarge);
+            }
+            if self.len as usize % ERASE_SIZE == 0 && self.len as usize > ERASE_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered sector, except for the first one
+    fn flush(&mut self) -> Result<(), AssetError> {
+        let sector = (self.len as usize - 1) / ERASE_SIZE * ERASE_SIZE;
+        let offset = ASSETS_OFFSET + sector as u32;
+        let filled = self.len as usize - sector;
+        self.buf[filled..].fill(0xFF);
+        flash::erase(offset, offset + ERASE_SIZE as u32)
+            .and_then(|()| flash::write(offset, &self.buf))
+            .map_err(|_| AssetError::Flash)
+    }
+
+    /// Checks the bundle and makes it visible by writing the first sector
+    pub fn finish(&mut self) -> Result<u16, AssetError> {
+        let header = self.header.ok_or(AssetError::Invalid)?;
+        if self.len as usize != header.size() {
+            warn!("Asset bundle truncated");
+            return Err(AssetError::Invalid);
+        }
+        if self.len as usize > ERASE_SIZE && self.len as usize % ERASE_SIZE != 0 {
+            self.flush()?;
+        }
+        if self.crc.finish() != header.crc {
+            warn!("Asset bundle checksum mismatch");
+            return Err(AssetError::Checksum);
+        }
+
+        // read back what ended up in flash behind the first sector
+        let mut crc = Crc32::new();
+        let first = self.len.min(ERASE_SIZE as u32) as usize;
+        crc.update(&self.first[HEADER_SIZE..first]);
+        crc.update(&assets_region()[ERASE_SIZE.min(self.len as usize)..self.len as usize]);
+        if crc.finish() != header.crc {
+            warn!("Asset bundle verification failed");
+            return Err(AssetError::Flash);
+        }
+
+        if table::entries(&self.first).is_err() {
+            return Err(AssetError::Invalid);
+        }
+
+        self.first[first..].fill(0xFF);
+        flash::write(ASSETS_OFFSET, &self.first).map_err(|_| AssetError::Flash)?;
+        update_region(|region| region.uploaded = true);
+        info!("Asset bundle of {} files stored", header.count);
+        Ok(header.count)
+    }
+}
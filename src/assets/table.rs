@@ -0,0 +1,256 @@
+//! Format of the asset bundles in the `STATIC` and `ASSETS` flash regions, shared with
+//! `build.rs` and `tools/asset-bundle`. All numbers are little endian.
+//!
+//! - header: magic, format version, entry count, length of everything after the header,
+//!   CRC-32 of it
+//! - entries: path and content type (NUL padded), offset from the bundle start, length,
+//!   CRC-32 of the data, flags
+//! - file data
+
+use crate::crc32::crc32;
+
+pub const MAGIC: u32 = 0x4241_4452;
+/// Bundles of another version are refused
+pub const VERSION: u16 = 2;
+pub const HEADER_SIZE: usize = 32;
+pub const ENTRY_SIZE: usize = 96;
+pub const MAX_PATH_LEN: usize = 48;
+pub const MAX_CONTENT_TYPE_LEN: usize = 32;
+/// The entry table has to fit into the first flash sector
+pub const MAX_ENTRIES: usize = (4096 - HEADER_SIZE) / ENTRY_SIZE;
+/// Entry flag: the data is gzip compressed, the path also has an entry with the plain data
+pub const FLAG_GZIP: u32 = 1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TableError {
+    /// No bundle at all, like erased flash
+    Missing,
+    /// Written by an incompatible build
+    Version(u16),
+    /// Truncated, entries out of bounds or checksum mismatch
+    Corrupt,
+}
+
+fn u16_at(data: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes(data[i..i + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(data[i..i + 4].try_into().unwrap())
+}
+
+fn str_at(data: &[u8], i: usize, len: usize) -> Option<&str> {
+    let field = &data[i..i + len];
+    let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+    core::str::from_utf8(&field[..end]).ok()
+}
+
+fn put_str(out: &mut [u8], i: usize, len: usize, s: &str) {
+    out[i..i + len].fill(0);
+    out[i..i + s.len()].copy_from_slice(s.as_bytes());
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub count: u16,
+    /// Length of the entry table and data
+    pub len: u32,
+    pub crc: u32,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, TableError> {
+        let data = data.get(..HEADER_SIZE).ok_or(TableError::Missing)?;
+        if u32_at(data, 0) != MAGIC {
+            return Err(TableError::Missing);
+        }
+        let version = u16_at(data, 4);
+        if version != VERSION {
+            return Err(TableError::Version(version));
+        }
+        let header = Header {
+            count: u16_at(data, 6),
+            len: u32_at(data, 8),
+            crc: u32_at(data, 12),
+        };
+        let table = (header.count as usize * ENTRY_SIZE) as u32;
+        if header.count as usize > MAX_ENTRIES || table > header.len {
+            return Err(TableError::Corrupt);
+        }
+        Ok(header)
+    }
+
+    pub fn write(&self, out: &mut [u8]) {
+        out[..HEADER_SIZE].fill(0);
+        out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        out[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        out[6..8].copy_from_slice(&self.count.to_le_bytes());
+        out[8..12].copy_from_slice(&self.len.to_le_bytes());
+        out[12..16].copy_from_slice(&self.crc.to_le_bytes());
+    }
+
+    /// Size of the whole bundle
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.len as usize
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Entry<'a> {
+    /// Without leading slash, like `www/index.html`
+    pub path: &'a str,
+    pub content_type: &'a str,
+    /// From the start of the bundle
+    pub offset: u32,
+    pub len: u32,
+    pub crc: u32,
+    pub gzip: bool,
+}
+
+impl<'a> Entry<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let data = data.get(..ENTRY_SIZE)?;
+        Some(Entry {
+            path: str_at(data, 0, MAX_PATH_LEN)?,
+            content_type: str_at(data, MAX_PATH_LEN, MAX_CONTENT_TYPE_LEN)?,
+            offset: u32_at(data, 80),
+            len: u32_at(data, 84),
+            crc: u32_at(data, 88),
+            gzip: u32_at(data, 92) & FLAG_GZIP != 0,
+        })
+    }
+
+    pub fn write(&self, out: &mut [u8]) {
+        put_str(out, 0, MAX_PATH_LEN, self.path);
+        put_str(out, MAX_PATH_LEN, MAX_CONTENT_TYPE_LEN, self.content_type);
+        out[80..84].copy_from_slice(&self.offset.to_le_bytes());
+        out[84..88].copy_from_slice(&self.len.to_le_bytes());
+        out[88..92].copy_from_slice(&self.crc.to_le_bytes());
+        let flags = if self.gzip { FLAG_GZIP } else { 0 };
+        out[92..96].copy_from_slice(&flags.to_le_bytes());
+    }
+
+    pub fn data(&self, bundle: &'a [u8]) -> &'a [u8] {
+        &bundle[self.offset as usize..(self.offset + self.len) as usize]
+    }
+}
+
+/// Entries of a bundle whose header and table are sane. Neither the checksum nor whether the
+/// file data is present is checked, so the table can be inspected before the rest arrived.
+pub fn entries(bundle: &[u8]) -> Result<impl Iterator<Item = Entry<'_>> + Clone, TableError> {
+    let header = Header::parse(bundle)?;
+    let size = header.size();
+    let table = bundle
+        .get(HEADER_SIZE..HEADER_SIZE + header.count as usize * ENTRY_SIZE)
+        .ok_or(TableError::Corrupt)?;
+    let entries = table.chunks(ENTRY_SIZE).map(Entry::parse);
+    let valid = entries.clone().all(|entry| {
+        entry.is_some_and(|e| {
+            e.offset
+                .checked_add(e.len)
+                .is_some_and(|end| end as usize <= size)
+        })
+    });
+    if !valid {
+        return Err(TableError::Corrupt);
+    }
+    Ok(entries.flatten())
+}
+
+/// Checks header, entries and checksum of the whole bundle
+pub fn validate(bundle: &[u8]) -> Result<Header, TableError> {
+    let header = Header::parse(bundle)?;
+    let _ = entries(bundle)?;
+    if header.size() > bundle.len() || crc32(&bundle[HEADER_SIZE..header.size()]) != header.crc {
+        return Err(TableError::Corrupt);
+    }
+    Ok(header)
+}
+
+/// Looks up the plain data of a file, or the compressed one if `gzip` and there is one
+pub fn find<'a>(bundle: &'a [u8], path: &str, gzip: bool) -> Option<Entry<'a>> {
+    let mut entries = entries(bundle).ok()?.filter(|entry| entry.path == path);
+    let plain = entries.clone().find(|entry| !entry.gzip);
+    match gzip {
+        true => entries.find(|entry| entry.gzip).or(plain),
+        false => plain,
+    }
+}
+
+/// Guessed from the extension, the browser sniffs anything unknown
+pub fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map_or("", |(_, e)| e);
+    match extension {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether gzip is worth a try, fonts and images are compressed already
+pub fn compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("image/svg+xml")
+}
+
+/// Packs `(path, content)` pairs into a bundle. Compressible files get a second entry with
+/// the output of `gzip`, if that is smaller.
+#[cfg(not(target_os = "none"))]
+pub fn build(
+    files: &[(String, Vec<u8>)],
+    gzip: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let mut contents = Vec::new();
+    for (path, content) in files {
+        if path.len() > MAX_PATH_LEN {
+            return Err(format!("{path} is longer than {MAX_PATH_LEN} characters"));
+        }
+        contents.push((path, content.clone(), false));
+        if compressible(content_type(path)) {
+            let compressed = gzip(content);
+            if compressed.len() < content.len() {
+                contents.push((path, compressed, true));
+            }
+        }
+    }
+    if contents.len() > MAX_ENTRIES {
+        return Err(format!(
+            "{} entries, at most {MAX_ENTRIES} fit",
+            contents.len()
+        ));
+    }
+
+    let data_start = HEADER_SIZE + contents.len() * ENTRY_SIZE;
+    let mut bundle = vec![0; data_start];
+    for (i, (path, content, gzip)) in contents.iter().enumerate() {
+        let entry = Entry {
+            path,
+            content_type: content_type(path),
+            offset: bundle.len() as u32,
+            len: content.len() as u32,
+            crc: crc32(content),
+            gzip: *gzip,
+        };
+        let start = HEADER_SIZE + i * ENTRY_SIZE;
+        entry.write(&mut bundle[start..start + ENTRY_SIZE]);
+        bundle.extend_from_slice(content);
+    }
+
+    let header = Header {
+        count: contents.len() as u16,
+        len: (bundle.len() - HEADER_SIZE) as u32,
+        crc: crc32(&bundle[HEADER_SIZE..]),
+    };
+    header.write(&mut bundle);
+    Ok(bundle)
+}
@@ -0,0 +1,61 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+pub use robodog_core::command::{CommandError, CommandResult, ServoTask};
+use robodog_core::control::Scheduler;
+
+static SCHEDULER: Mutex<CriticalSectionRawMutex, RefCell<Scheduler>> =
+    Mutex::new(RefCell::new(Scheduler::new()));
+/// Wakes the servo task before its next tick when there is something to do
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Runs `f` on the scheduler shared by the handlers and the servo task
+pub(crate) fn with<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    SCHEDULER.lock(|scheduler| f(&mut scheduler.borrow_mut()))
+}
+
+/// Queues a task for the servo task and returns its sequence id
+pub fn submit(task: ServoTask) -> Result<u32, CommandError> {
+    let seq = with(|scheduler| scheduler.submit(task))?;
+    wake();
+    Ok(seq)
+}
+
+/// Streams a pose target for teleoperation. Only the latest one is kept and it replaces
+/// the running motion instead of waiting for it to finish.
+pub fn stream(x: f32, y: f32, z: f32) -> Result<(), CommandError> {
+    let now_ms = Instant::now().as_millis();
+    with(|scheduler| scheduler.stream([x, y, z], now_ms))?;
+    wake();
+    Ok(())
+}
+
+/// Returns the last known result of a command, `None` if it is unknown or too old
+pub fn result(seq: u32) -> Option<CommandResult> {
+    with(|scheduler| scheduler.result(seq))
+}
+
+/// Waits until the command has finished or the timeout has passed
+pub async fn wait(seq: u32, timeout: Duration) -> Option<CommandResult> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let result = result(seq);
+        match result {
+            Some(r) if !r.is_finished() && Instant::now() < deadline => {
+                Timer::after_millis(10).await
+            }
+            _ => return result,
+        }
+    }
+}
+
+pub(crate) fn wake() {
+    WAKE.signal(());
+}
+
+/// Waits until a command, setpoint or emergency stop needs the servo task
+pub(crate) async fn woken() {
+    WAKE.wait().await
+}
@@ -0,0 +1,18 @@
+use crate::control::command;
+use embassy_time::Instant;
+pub use robodog_core::control::{Deadman, SafeAction, Trip};
+
+pub fn state() -> Deadman {
+    command::with(|scheduler| scheduler.deadman())
+}
+
+pub fn configure(timeout_ms: u32, action: SafeAction) {
+    command::with(|scheduler| scheduler.configure_deadman(timeout_ms, action));
+}
+
+/// Extends the lease only if it is armed, so heartbeats alone never start motion supervision.
+/// Only streamed motion arms it, queued commands run to the end without heartbeats.
+pub fn heartbeat() {
+    let now_ms = Instant::now().as_millis();
+    command::with(|scheduler| scheduler.heartbeat(now_ms));
+}
@@ -0,0 +1,19 @@
+use crate::control::command;
+use embassy_time::Instant;
+pub use robodog_core::control::{EStop, EStopMode, EStopSource};
+
+/// Latches the emergency stop. A later trigger can only escalate from freeze to relax.
+pub fn trigger(source: EStopSource, mode: EStopMode) {
+    let now_ms = Instant::now().as_millis();
+    command::with(|scheduler| scheduler.trigger_estop(source, mode, now_ms));
+    command::wake();
+}
+
+/// Releases the latch, motion commands are accepted again afterwards
+pub fn reset() {
+    command::with(|scheduler| scheduler.reset_estop());
+}
+
+pub fn state() -> Option<EStop> {
+    command::with(|scheduler| scheduler.estop())
+}
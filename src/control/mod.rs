@@ -0,0 +1,6 @@
+pub mod command;
+pub mod deadman;
+pub mod estop;
+pub mod status;
+pub mod telemetry;
+pub mod teleop;
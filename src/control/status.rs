@@ -0,0 +1,22 @@
+use crate::control::command;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::PubSubChannel;
+pub use robodog_core::control::{MotionEvent, Status};
+pub use robodog_core::servo::MotionState;
+
+pub const EVENT_SUBSCRIBERS: usize = 4;
+pub static MOTION_EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    MotionEvent,
+    8,
+    EVENT_SUBSCRIBERS,
+    1,
+> = PubSubChannel::new();
+
+pub fn status() -> Status {
+    command::with(|scheduler| scheduler.status())
+}
+
+pub(crate) fn publish(event: MotionEvent) {
+    MOTION_EVENTS.immediate_publisher().publish_immediate(event);
+}
@@ -0,0 +1,24 @@
+use crate::peripheral::servo::Legs;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+use robodog_core::telemetry::Recorder;
+pub use robodog_core::telemetry::Telemetry;
+
+static TELEMETRY: Mutex<CriticalSectionRawMutex, RefCell<Recorder>> =
+    Mutex::new(RefCell::new(Recorder::new()));
+
+pub fn snapshot() -> Telemetry {
+    TELEMETRY.lock(|recorder| recorder.borrow().snapshot())
+}
+
+pub(crate) fn record_legs(legs: &Legs) {
+    TELEMETRY.lock(|recorder| recorder.borrow_mut().record_legs(legs));
+}
+
+/// Called on every control loop tick to measure its period
+pub(crate) fn record_tick() {
+    let now_us = Instant::now().as_micros();
+    TELEMETRY.lock(|recorder| recorder.borrow_mut().record_tick(now_us));
+}
@@ -0,0 +1,88 @@
+use crate::control::{command, deadman};
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+pub use robodog_core::legs::in_range;
+
+/// The velocity is dropped if the client has been silent for this long
+const VELOCITY_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Format)]
+pub enum TeleopError {
+    OutOfRange,
+    EStopped,
+}
+
+impl TeleopError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            TeleopError::OutOfRange => "coordinates out of range",
+            TeleopError::EStopped => "emergency stop latched",
+        }
+    }
+}
+
+/// Pose streaming state of one remote client
+pub struct Teleop {
+    pose: Option<[f32; 3]>,
+    /// Velocity of the foot target in mm/s, applied relative to the last pose
+    velocity: Option<[f32; 3]>,
+    last_message: Instant,
+}
+
+impl Teleop {
+    pub fn new() -> Self {
+        Self {
+            pose: None,
+            velocity: None,
+            last_message: Instant::now(),
+        }
+    }
+
+    /// Has to be called for every message of the client, keeps the deadman lease alive
+    pub fn received(&mut self) {
+        deadman::heartbeat();
+        self.last_message = Instant::now();
+    }
+
+    pub fn set_pose(&mut self, pose: [f32; 3]) -> Result<(), TeleopError> {
+        if !in_range(pose) {
+            return Err(TeleopError::OutOfRange);
+        }
+        command::stream(pose[0], pose[1], pose[2]).map_err(|_| TeleopError::EStopped)?;
+        self.pose = Some(pose);
+        Ok(())
+    }
+
+    /// Replaces the velocity, a zero velocity stops
+    pub fn set_velocity(&mut self, velocity: [f32; 3]) {
+        self.velocity = Some(velocity);
+    }
+
+    pub fn stop(&mut self) {
+        self.velocity = None;
+    }
+
+    pub fn pose(&self) -> Option<[f32; 3]> {
+        self.pose
+    }
+
+    /// Moves the pose along the velocity, does nothing until a pose has been set
+    pub fn tick(&mut self, dt: Duration) {
+        if self.last_message.elapsed() > VELOCITY_TIMEOUT {
+            self.velocity = None;
+        }
+        let (Some(pose), Some(velocity)) = (self.pose, self.velocity) else {
+            return;
+        };
+
+        let dt = dt.as_millis() as f32 / 1000.0;
+        let mut next = pose;
+        for (p, v) in next.iter_mut().zip(velocity) {
+            *p += v * dt;
+        }
+        if self.set_pose(next).is_err() {
+            self.velocity = None;
+        }
+    }
+}
@@ -1,13 +1,19 @@
+/// A file listed in `flash_files`, looked up in the asset table. Depending on the build mode,
+/// the table lives in the `STATIC` region or is compiled into the firmware, see `build.rs`.
 #[macro_export]
 macro_rules! include_flash_bytes {
     ($file:expr) => {
-        unsafe { include!(concat!(env!("OUT_DIR"), "/", $file)) }
+        $crate::assets::builtin_bytes($file)
     };
 }
 
+/// Like [`include_flash_bytes`], for text files. Panics if the file is not UTF-8.
 #[macro_export]
 macro_rules! include_flash_str {
     ($file:expr) => {
-        str::from_utf8(unsafe { include!(concat!(env!("OUT_DIR"), "/", $file)) }).unwrap()
+        match core::str::from_utf8($crate::include_flash_bytes!($file)) {
+            Ok(s) => s,
+            Err(_) => panic!("{} is not UTF-8", $file),
+        }
     };
 }
@@ -2,22 +2,41 @@
 #![no_main]
 #![feature(impl_trait_in_assoc_type)]
 
+mod assets;
+mod control;
+mod crc32;
 mod macros;
-mod model;
 mod net;
+mod ota;
 mod peripheral;
-
-use crate::net::app::{AppProps, WEB_TASK_POOL_SIZE, app_task};
-use crate::net::network::{Network, net_task};
+mod settings;
+
+use crate::net::app::{WEB_TASK_POOL_SIZE, app_task};
+use crate::net::auth;
+use crate::net::dhcp::{Pool, dhcp_task};
+use crate::net::dns::dns_task;
+use crate::net::mdns::{self, mdns_task};
+use crate::net::network::{Network, WifiMode, net_task};
+use crate::net::udp::udp_task;
+use crate::ota::confirm_task;
+use crate::peripheral::button::estop_button_task;
 use crate::peripheral::cyw43::{Cyw43, cyw43_task};
+use crate::peripheral::flash;
 use crate::peripheral::servo::{Servo, ServoConfig, servo_task};
+use crate::peripheral::watchdog::{self, Supervised, watchdog_task};
+use defmt::info;
 use embassy_executor::Spawner;
+use embassy_net::Ipv4Address;
+use embassy_rp::flash::Flash;
+use embassy_rp::gpio::{Input, Pull};
 use embassy_rp::peripherals::PIO0;
 use embassy_rp::pio::{InterruptHandler, Pio};
 use embassy_rp::pwm::{Config, Pwm};
+use embassy_rp::watchdog::Watchdog;
 use embassy_rp::{bind_interrupts, init};
 use embassy_time::{Duration, Timer};
-use picoserve::{AppRouter, AppWithStateBuilder, make_static};
+use heapless::String;
+use picoserve::make_static;
 #[allow(unused)]
 use {defmt_rtt as _, panic_probe as _};
 
@@ -28,6 +47,13 @@ bind_interrupts!(struct Irqs {
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = init(Default::default());
+    // the bootloader's watchdog is still running, joining a network takes longer than it allows
+    spawner.must_spawn(watchdog_task(Watchdog::new(p.WATCHDOG)));
+    flash::init(Flash::new_blocking(p.FLASH));
+    assets::init();
+    let settings = settings::load();
+    auth::set_password(settings.password());
+
     let Pio {
         mut common,
         irq0,
@@ -48,12 +74,35 @@ async fn main(spawner: Spawner) {
     .await;
     spawner.must_spawn(cyw43_task(cyw43_runner));
 
-    cyw43.init().await;
+    cyw43.init(settings.power_management).await;
     let (mut net, net_runner) = Network::new(net_device);
     spawner.must_spawn(net_task(net_runner));
 
-    cyw43.create_ap("robodog_ap", "robodogg").await;
-    net.up().await;
+    let mut mode = WifiMode::AccessPoint;
+    if let Some(station) = &settings.station {
+        info!("joining {}", station.ssid.as_str());
+        if cyw43
+            .join_wifi(&station.ssid, &station.password, Duration::from_secs(15))
+            .await
+        {
+            net.use_dhcp(settings.hostname());
+            if net
+                .up_within(WifiMode::Station, Duration::from_secs(15))
+                .await
+            {
+                mode = WifiMode::Station;
+            } else {
+                info!("no DHCP lease, falling back to access point");
+                cyw43.leave().await;
+            }
+        }
+    }
+    if mode == WifiMode::AccessPoint {
+        let ap = &settings.access_point;
+        net.use_static(Ipv4Address::from(ap.address), ap.prefix_len);
+        cyw43.create_ap(&ap.ssid, &ap.password, ap.channel).await;
+        net.up(WifiMode::AccessPoint).await;
+    }
 
     let hz = 50;
     let div = 48;
@@ -80,28 +129,31 @@ async fn main(spawner: Spawner) {
     #[allow(unused_variables)]
     let mg90s_config = ServoConfig::new(1.0 / 20.0, 1.5 / 20.0, 2.0 / 20.0, 180, 0, false, false);
 
-    let servo_0 = Servo::new(pwm_0.unwrap(), ServoConfig::hip(0.05, 0.068, 0.1));
-    let servo_1 = Servo::new(pwm_1.unwrap(), ServoConfig::thigh(0.073, 0.088, 0.115));
-    let servo_2 = Servo::new(pwm_2.unwrap(), ServoConfig::foot(0.028, 0.0678, 0.118));
+    let [c0, c1, c2, c4, c5, c6, c8, c9, c10, c12, c13, c14] = ServoConfig::calibration();
+    let servo_0 = Servo::new(pwm_0.unwrap(), c0);
+    let servo_1 = Servo::new(pwm_1.unwrap(), c1);
+    let servo_2 = Servo::new(pwm_2.unwrap(), c2);
 
-    let servo_4 = Servo::new(pwm_4.unwrap(), ServoConfig::hip(0.06, 0.088, 0.105));
-    let servo_5 = Servo::new(pwm_5.unwrap(), ServoConfig::thigh(0.056, 0.085, 0.102));
-    let servo_6 = Servo::new(pwm_6.unwrap(), ServoConfig::foot(0.0275, 0.077, 0.119));
+    let servo_4 = Servo::new(pwm_4.unwrap(), c4);
+    let servo_5 = Servo::new(pwm_5.unwrap(), c5);
+    let servo_6 = Servo::new(pwm_6.unwrap(), c6);
 
-    let servo_8 = Servo::new(pwm_8.unwrap(), ServoConfig::hip(0.04, 0.060, 0.093));
-    let servo_9 = Servo::new(pwm_9.unwrap(), ServoConfig::thigh(0.07, 0.1, 0.12));
-    let servo_10 = Servo::new(pwm_10.unwrap(), ServoConfig::foot(0.035, 0.086, 0.119));
+    let servo_8 = Servo::new(pwm_8.unwrap(), c8);
+    let servo_9 = Servo::new(pwm_9.unwrap(), c9);
+    let servo_10 = Servo::new(pwm_10.unwrap(), c10);
 
-    let servo_12 = Servo::new(pwm_12.unwrap(), ServoConfig::hip(0.055, 0.085, 0.1));
-    let servo_13 = Servo::new(pwm_13.unwrap(), ServoConfig::thigh(0.07, 0.1, 0.12));
-    let servo_14 = Servo::new(pwm_14.unwrap(), ServoConfig::foot(0.03, 0.08, 0.125));
+    let servo_12 = Servo::new(pwm_12.unwrap(), c12);
+    let servo_13 = Servo::new(pwm_13.unwrap(), c13);
+    let servo_14 = Servo::new(pwm_14.unwrap(), c14);
 
     spawner.must_spawn(servo_task([
         servo_0, servo_1, servo_2, servo_4, servo_5, servo_6, servo_8, servo_9, servo_10, servo_12,
         servo_13, servo_14,
     ]));
 
-    let app = make_static!(AppRouter<AppProps>, AppProps.build_app());
+    // optional, the pull-up keeps it released when nothing is connected
+    spawner.must_spawn(estop_button_task(Input::new(p.PIN_16, Pull::Up)));
+
     let config = make_static!(
         picoserve::Config<Duration>,
         picoserve::Config::new(picoserve::Timeouts {
@@ -114,15 +166,36 @@ async fn main(spawner: Spawner) {
     );
 
     for i in 0..WEB_TASK_POOL_SIZE {
-        spawner.must_spawn(app_task(i, net.stack, app, config))
+        spawner.must_spawn(app_task(i, net.stack, config))
+    }
+    spawner.must_spawn(udp_task(net.stack));
+    cyw43.add_multicast_address(mdns::GROUP_MAC).await;
+    spawner.must_spawn(mdns_task(
+        net.stack,
+        String::try_from(settings.hostname()).unwrap(),
+    ));
+    if mode == WifiMode::AccessPoint {
+        let address = Ipv4Address::from(settings.access_point.address);
+        spawner.must_spawn(dhcp_task(net.stack, Pool::new(&settings.access_point)));
+        spawner.must_spawn(dns_task(net.stack, address));
     }
 
-    let delay = Duration::from_secs(1);
+    watchdog::supervise();
+    spawner.must_spawn(confirm_task());
+
+    // station mode blinks briefly, the access point blinks evenly
+    let (off, on) = match mode {
+        WifiMode::Station => (Duration::from_millis(200), Duration::from_millis(1800)),
+        WifiMode::AccessPoint => (Duration::from_secs(1), Duration::from_secs(1)),
+    };
     loop {
+        // every LED update goes through the cyw43 runner, so this also proves it is alive
         cyw43.set_led(false).await;
-        Timer::after(delay).await;
+        watchdog::check_in(Supervised::Cyw43);
+        Timer::after(off).await;
 
         cyw43.set_led(true).await;
-        Timer::after(delay).await;
+        watchdog::check_in(Supervised::Cyw43);
+        Timer::after(on).await;
     }
 }
@@ -0,0 +1,131 @@
+use crate::control::command::{self, CommandResult, ServoTask};
+use crate::control::{deadman, estop, status};
+use crate::net::auth;
+use crate::net::network;
+use crate::ota;
+use crate::peripheral::servo::LEG_COUNT;
+use crate::peripheral::watchdog;
+use crate::settings::{self, Settings, Station};
+use embassy_net::IpAddress;
+use embassy_time::Instant;
+use picoserve::response::{Json, StatusCode};
+use robodog_core::api::{
+    Accepted, ErrorBody, Firmware, Hostname, Joints, Login, NetworkState, Position, Session,
+    SettingsUpdate, SettingsView, State,
+};
+use robodog_core::command::CommandError;
+
+pub type ApiError = (StatusCode, Json<ErrorBody<'static>>);
+pub type ApiResult<T> = Result<(StatusCode, Json<T>), ApiError>;
+
+pub(crate) fn error(status: StatusCode, message: &'static str) -> ApiError {
+    (status, Json(ErrorBody { error: message }))
+}
+
+/// Answers with the status code and message `robodog_core::api` has for `err`
+pub(crate) fn reject(err: impl Into<robodog_core::api::ApiError>) -> ApiError {
+    let err = err.into();
+    (StatusCode::new(err.status), Json(err.body()))
+}
+
+fn submit(task: ServoTask) -> ApiResult<Accepted> {
+    let seq = command::submit(task).map_err(reject)?;
+    Ok((StatusCode::ACCEPTED, Json(Accepted { seq })))
+}
+
+pub fn pose(pos: Position) -> ApiResult<Accepted> {
+    pos.validate().map_err(reject)?;
+    submit(ServoTask::MOVE(pos.x, pos.y, pos.z))
+}
+
+pub fn leg(id: u8, pos: Position) -> ApiResult<Accepted> {
+    if id as usize >= LEG_COUNT {
+        return Err(reject(CommandError::InvalidLeg));
+    }
+    pos.validate().map_err(reject)?;
+    submit(ServoTask::LEG(id, pos.x, pos.y, pos.z))
+}
+
+pub fn joints(joints: Joints) -> ApiResult<Accepted> {
+    joints.validate().map_err(reject)?;
+    submit(ServoTask::JOINTS(joints.angles))
+}
+
+pub fn command(seq: u32) -> ApiResult<CommandResult> {
+    match command::result(seq) {
+        Some(result) => Ok((StatusCode::OK, Json(result))),
+        None => Err(error(StatusCode::NOT_FOUND, "unknown command")),
+    }
+}
+
+pub fn state() -> Json<State> {
+    Json(State {
+        motion: status::status(),
+        estop: estop::state(),
+        deadman: deadman::state().into(),
+        reset: watchdog::report().reset,
+        network: network::info(),
+        uptime_ms: Instant::now().as_millis(),
+    })
+}
+
+pub fn network() -> Json<NetworkState> {
+    Json(NetworkState::new(&settings::load(), network::info()))
+}
+
+/// Stores the network to join, `None` clears it so only the access point is opened
+pub fn set_station(station: Option<Station>) -> ApiResult<NetworkState> {
+    let mut settings = settings::load();
+    settings.station = station;
+    settings::save(&settings).map_err(reject)?;
+    Ok((StatusCode::OK, network()))
+}
+
+/// Stores the hostname used for DHCP and mDNS from the next boot on
+pub fn set_hostname(hostname: Hostname) -> ApiResult<NetworkState> {
+    let mut settings = settings::load();
+    settings.hostname = Some(hostname.hostname);
+    settings::save(&settings).map_err(reject)?;
+    Ok((StatusCode::OK, network()))
+}
+
+pub fn settings() -> Json<SettingsView> {
+    Json(SettingsView::from(&settings::load()))
+}
+
+pub fn update_settings(update: SettingsUpdate) -> ApiResult<SettingsView> {
+    let mut settings = settings::load();
+    update.apply(&mut settings);
+    settings::save(&settings).map_err(reject)?;
+    auth::set_password(settings.password());
+    Ok((StatusCode::OK, self::settings()))
+}
+
+/// Restores the defaults, the password right away and the access point on the next boot
+pub fn reset_settings() -> ApiResult<SettingsView> {
+    settings::reset().map_err(reject)?;
+    auth::set_password(Settings::default().password());
+    Ok((StatusCode::OK, settings()))
+}
+
+pub fn reboot() -> StatusCode {
+    watchdog::reboot();
+    StatusCode::ACCEPTED
+}
+
+pub fn login(peer: IpAddress, login: Login) -> ApiResult<Session> {
+    let token = auth::login(peer, &login.password).map_err(reject)?;
+    Ok((StatusCode::OK, Json(Session { token })))
+}
+
+pub fn logout(token: &str) -> StatusCode {
+    auth::logout(token);
+    StatusCode::NO_CONTENT
+}
+
+pub fn firmware() -> Json<Firmware> {
+    Json(Firmware {
+        version: env!("CARGO_PKG_VERSION"),
+        state: ota::state(),
+    })
+}
@@ -1,9 +1,28 @@
-use crate::peripheral::servo::{SERVO_SIGNAL, ServoTask};
-use crate::{include_flash_bytes, include_flash_str};
+use crate::control::command::{self, CommandError, ServoTask};
+use crate::control::deadman::{self, SafeAction};
+use crate::control::estop::{self, EStopMode, EStopSource};
+use crate::control::status;
+use crate::net::api;
+use crate::net::auth::{Authorized, Peer};
+use crate::net::clients;
+use crate::net::events::{DEFAULT_INTERVAL_MS, TelemetryEvents};
+use crate::net::files::{self, AssetUpload, StaticFiles};
+use crate::net::firmware::FirmwareUpload;
+use crate::net::network;
+use crate::net::ws::StreamHandler;
+use crate::peripheral::watchdog;
+use crate::settings::Station;
+use defmt::{Debug2Format, debug, warn};
+use embassy_futures::select::{Either, select};
+use embassy_net::tcp::TcpSocket;
 use embassy_time::Duration;
-use picoserve::response::{DebugValue, File, Redirect};
-use picoserve::routing::{PathRouter, get, get_service, parse_path_segment};
-use picoserve::{AppBuilder, AppRouter, Router, Server};
+use picoserve::extract::{Json, State};
+use picoserve::response::{
+    DebugValue, EventStream, IntoResponse, Redirect, StatusCode, WebSocketUpgrade,
+};
+use picoserve::routing::{PathRouter, get, parse_path_segment, post, post_service};
+use picoserve::{AppWithStateBuilder, Router, Server};
+use robodog_core::api::{Hostname, Joints, Login, Position, SettingsUpdate};
 
 pub const WEB_TASK_POOL_SIZE: usize = 8;
 
@@ -11,7 +30,6 @@ pub const WEB_TASK_POOL_SIZE: usize = 8;
 pub async fn app_task(
     id: usize,
     stack: embassy_net::Stack<'static>,
-    app: &'static AppRouter<AppProps>,
     config: &'static picoserve::Config<Duration>,
 ) -> ! {
     let port = 80;
@@ -19,47 +37,72 @@ pub async fn app_task(
     let mut tcp_tx_buffer = [0; 1024];
     let mut http_buffer = [0; 2048];
 
-    Server::new(app, config, &mut http_buffer)
-        .listen_and_serve(id, stack, port, &mut tcp_rx_buffer, &mut tcp_tx_buffer)
-        .await
-        .into_never()
+    // accepts like `Server::listen_and_serve`, but records the peer for the client count
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut tcp_rx_buffer, &mut tcp_tx_buffer);
+        if let Err(err) = socket.accept(port).await {
+            warn!("{}: accept error: {}", id, err);
+            continue;
+        }
+        let Some(remote) = socket.remote_endpoint() else {
+            continue;
+        };
+        socket.set_keep_alive(Some(Duration::from_secs(30)));
+        socket.set_timeout(Some(Duration::from_secs(45)));
+
+        // built for every connection, the routes know who they are talking to
+        let app = AppProps.build_app().with_state(Peer(remote.addr));
+        let server = Server::new(&app, config, &mut http_buffer);
+        // event streams and websockets stay open for a long time, they count while they do
+        if let Either::First(Err(err)) =
+            select(server.serve(socket), clients::connected(remote.addr)).await
+        {
+            debug!(
+                "{}: connection from {} closed: {}",
+                id,
+                remote,
+                Debug2Format(&err)
+            );
+        }
+    }
+}
+
+fn submit(task: ServoTask) -> impl IntoResponse {
+    match command::submit(task) {
+        Ok(seq) => Ok(DebugValue(seq)),
+        Err(err @ CommandError::EStopped) => Err((StatusCode::CONFLICT, DebugValue(err))),
+        Err(err) => Err((StatusCode::SERVICE_UNAVAILABLE, DebugValue(err))),
+    }
 }
 
 pub struct AppProps;
 
-impl AppBuilder for AppProps {
-    type PathRouter = impl PathRouter;
+impl AppWithStateBuilder for AppProps {
+    type State = Peer;
+    type PathRouter = impl PathRouter<Peer>;
 
-    fn build_app(self) -> Router<Self::PathRouter> {
-        Router::new()
+    fn build_app(self) -> Router<Self::PathRouter, Peer> {
+        // files of `www/` and of the uploaded asset bundle
+        Router::from_service(StaticFiles)
             .route("/", get(|| async move { Redirect::to("/index.html") }))
+            // connectivity checks of Android, Apple, Windows and Firefox, answered by the
+            // captive portal DNS in AP mode. The absolute URL makes the portal browser open
+            // the robot and not the probed host.
+            .route("/generate_204", get(|| async move { Redirect::to(network::index_url()) }))
+            .route("/gen_204", get(|| async move { Redirect::to(network::index_url()) }))
             .route(
-                "/index.html",
-                get_service(File::html(include_flash_str!("www/index.html"))),
-            )
-            .route(
-                "/calibrate.html",
-                get_service(File::html(include_flash_str!("www/calibrate.html"))),
-            )
-            .route(
-                "/index.css",
-                get_service(File::css(include_flash_str!("www/index.css"))),
-            )
-            .route(
-                "/JetBrainsMono-Regular.woff2",
-                get_service(File::with_content_type(
-                    "font/woff2",
-                    include_flash_bytes!("www/JetBrainsMono-Regular.woff2"),
-                )),
-            )
-            .route(
-                "/index.js",
-                get_service(File::javascript(include_flash_str!("www/index.js"))),
+                "/hotspot-detect.html",
+                get(|| async move { Redirect::to(network::index_url()) }),
             )
             .route(
-                "/calibrate.js",
-                get_service(File::javascript(include_flash_str!("www/calibrate.js"))),
+                "/library/test/success.html",
+                get(|| async move { Redirect::to(network::index_url()) }),
             )
+            .route("/connecttest.txt", get(|| async move { Redirect::to(network::index_url()) }))
+            .route("/ncsi.txt", get(|| async move { Redirect::to(network::index_url()) }))
+            .route("/redirect", get(|| async move { Redirect::to(network::index_url()) }))
+            .route("/canonical.html", get(|| async move { Redirect::to(network::index_url()) }))
+            .route("/success.txt", get(|| async move { Redirect::to(network::index_url()) }))
             .route(
                 (
                     "/pos",
@@ -67,28 +110,182 @@ impl AppBuilder for AppProps {
                     parse_path_segment(),
                     parse_path_segment(),
                 ),
-                get(|pos: (i16, i16, i16)| async move {
-                    SERVO_SIGNAL.signal(ServoTask::MOVE(
+                get(|pos: (i16, i16, i16), _: Authorized| async move {
+                    submit(ServoTask::MOVE(
                         pos.0 as f32 / 100.0,
                         pos.1 as f32 / 100.0,
                         pos.2 as f32 / 100.0,
-                    ));
-                    DebugValue(pos)
+                    ))
                 }),
             )
             .route(
                 ("/pwm", parse_path_segment(), parse_path_segment()),
-                get(|data: (u8, u16)| async move {
+                get(|data: (u8, u16), _: Authorized| async move {
                     let pwm = data.1 as f32 / 6666.66;
-                    SERVO_SIGNAL.signal(ServoTask::CALIBRATION(data.0, pwm));
-                    DebugValue(pwm)
+                    submit(ServoTask::CALIBRATION(data.0, pwm))
                 }),
             )
             .route(
                 "/home",
+                get(|_: Authorized| async move { submit(ServoTask::HOME) }),
+            )
+            .route(
+                "/relax",
+                get(|_: Authorized| async move { submit(ServoTask::RELAX) }),
+            )
+            // stopping is always allowed, without a session
+            .route("/stop", get(|| async move { submit(ServoTask::STOP) }))
+            .route(
+                "/estop",
+                get(|| async move {
+                    estop::trigger(EStopSource::Http, EStopMode::Freeze);
+                    DebugValue(estop::state())
+                }),
+            )
+            .route(
+                "/estop/relax",
                 get(|| async move {
-                    SERVO_SIGNAL.signal(ServoTask::HOME);
-                    DebugValue("Home")
+                    estop::trigger(EStopSource::Http, EStopMode::Relax);
+                    DebugValue(estop::state())
+                }),
+            )
+            .route(
+                "/estop/reset",
+                get(|_: Authorized| async move {
+                    estop::reset();
+                    DebugValue(estop::state())
+                }),
+            )
+            .route(
+                "/status",
+                get(|| async move { DebugValue(status::status()) }),
+            )
+            .route(
+                "/watchdog",
+                get(|| async move { DebugValue(watchdog::report()) }),
+            )
+            .route(
+                "/heartbeat",
+                get(|_: Authorized| async move {
+                    deadman::heartbeat();
+                    DebugValue(deadman::state().last_seen_ms.is_some())
+                }),
+            )
+            .route(
+                "/deadman",
+                get(|| async move { DebugValue(deadman::state()) }),
+            )
+            .route(
+                ("/deadman", parse_path_segment(), parse_path_segment()),
+                get(|config: (u32, SafeAction), _: Authorized| async move {
+                    deadman::configure(config.0, config.1);
+                    DebugValue(deadman::state())
+                }),
+            )
+            .route(
+                ("/cmd", parse_path_segment()),
+                get(|seq: u32| async move { DebugValue(command::result(seq)) }),
+            )
+            .route(
+                ("/cmd", parse_path_segment(), "/wait"),
+                get(|seq: u32| async move {
+                    DebugValue(command::wait(seq, Duration::from_secs(5)).await)
+                }),
+            )
+            .route(
+                "/api/v1/pose",
+                post(|_: Authorized, Json(pos): Json<Position, 0>| async move {
+                    api::pose(pos)
+                }),
+            )
+            .route(
+                "/api/v1/joints",
+                post(|_: Authorized, Json(joints): Json<Joints, 0>| async move {
+                    api::joints(joints)
+                }),
+            )
+            .route(
+                ("/api/v1/leg", parse_path_segment()),
+                post(
+                    |id: u8, _: Authorized, Json(pos): Json<Position, 0>| async move {
+                        api::leg(id, pos)
+                    },
+                ),
+            )
+            .route(
+                ("/api/v1/commands", parse_path_segment()),
+                get(|seq: u32| async move { api::command(seq) }),
+            )
+            .route("/api/v1/state", get(|| async move { api::state() }))
+            .route("/api/v1/network", get(|| async move { api::network() }))
+            .route(
+                "/api/v1/network/station",
+                post(
+                    |_: Authorized, Json(station): Json<Station, 64>| async move {
+                        api::set_station(Some(station))
+                    },
+                )
+                .delete(|_: Authorized| async move { api::set_station(None) }),
+            )
+            .route(
+                "/api/v1/settings",
+                get(|| async move { api::settings() })
+                    .post(
+                        |_: Authorized, Json(update): Json<SettingsUpdate, 64>| async move {
+                            api::update_settings(update)
+                        },
+                    )
+                    .delete(|_: Authorized| async move { api::reset_settings() }),
+            )
+            .route(
+                "/api/v1/reboot",
+                post(|_: Authorized| async move { api::reboot() }),
+            )
+            .route(
+                "/api/v1/assets",
+                post_service(AssetUpload).delete(|_: Authorized| async move { files::clear() }),
+            )
+            .route("/api/v1/firmware", get(|| async move { api::firmware() }))
+            .route(
+                ("/api/v1/firmware", parse_path_segment()),
+                post_service(FirmwareUpload),
+            )
+            .route(
+                "/api/v1/login",
+                post(
+                    |State(Peer(peer)): State<Peer>, Json(login): Json<Login, 64>| async move {
+                        api::login(peer, login)
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/logout",
+                post(|Authorized(token): Authorized| async move { api::logout(&token) }),
+            )
+            .route(
+                "/api/v1/session",
+                get(|_: Authorized| async move { StatusCode::NO_CONTENT }),
+            )
+            .route(
+                "/api/v1/network/hostname",
+                post(|_: Authorized, Json(hostname): Json<Hostname, 0>| async move {
+                    api::set_hostname(hostname)
+                }),
+            )
+            .route(
+                "/events",
+                get(|| async move { EventStream(TelemetryEvents::new(DEFAULT_INTERVAL_MS)) }),
+            )
+            .route(
+                ("/events", parse_path_segment()),
+                get(|interval_ms: u64| async move {
+                    EventStream(TelemetryEvents::new(interval_ms))
+                }),
+            )
+            .route(
+                "/ws",
+                get(|_: Authorized, upgrade: WebSocketUpgrade| async move {
+                    upgrade.on_upgrade(StreamHandler)
                 }),
             )
     }
@@ -0,0 +1,78 @@
+use crate::net::api::{self, ApiError};
+use core::cell::RefCell;
+use defmt::{info, warn};
+use embassy_net::IpAddress;
+use embassy_rp::clocks::RoscRng;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+use picoserve::extract::FromRequestParts;
+use picoserve::request::RequestParts;
+use robodog_core::auth::{self, Auth, AuthError, TOKEN_LEN, Token};
+
+/// Address of the client on the other end of the connection, the state of the app router
+#[derive(Copy, Clone)]
+pub struct Peer(pub IpAddress);
+
+static AUTH: Mutex<CriticalSectionRawMutex, RefCell<Auth<IpAddress>>> =
+    Mutex::new(RefCell::new(Auth::new()));
+
+fn with<R>(f: impl FnOnce(&mut Auth<IpAddress>) -> R) -> R {
+    AUTH.lock(|auth| f(&mut auth.borrow_mut()))
+}
+
+/// Sets the password for control routes, on boot and whenever the settings are saved
+pub fn set_password(password: &str) {
+    with(|auth| auth.set_password(password));
+}
+
+/// Checks the password and opens a session, see [Auth::login] for the lockout
+pub fn login(peer: IpAddress, password: &str) -> Result<Token, AuthError> {
+    let mut rng = RoscRng;
+    let mut token = [0; TOKEN_LEN];
+    token[..8].copy_from_slice(&rng.next_u64().to_le_bytes());
+    token[8..].copy_from_slice(&rng.next_u64().to_le_bytes());
+
+    let now_ms = Instant::now().as_millis();
+    with(|auth| match auth.login(peer, password, now_ms, token) {
+        Ok(token) => {
+            info!("Login from {}, {} sessions", peer, auth.sessions());
+            Ok(token)
+        }
+        Err(err) => {
+            warn!("Login from {} failed: {}", peer, err);
+            Err(err)
+        }
+    })
+}
+
+pub fn logout(token: &str) {
+    with(|auth| auth.logout(token));
+}
+
+/// Whether a client at `address` holds a session, for the UDP control which has no tokens
+pub fn is_authorized(address: IpAddress) -> bool {
+    with(|auth| auth.is_authorized(address))
+}
+
+/// Extractor for control routes, rejects requests without a valid session
+pub struct Authorized(pub Token);
+
+impl<'r> FromRequestParts<'r, Peer> for Authorized {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        peer: &'r Peer,
+        request_parts: &RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let headers = request_parts.headers();
+        let authorization = headers.get("Authorization").and_then(|v| v.as_str().ok());
+        let cookie = headers.get("Cookie").and_then(|v| v.as_str().ok());
+        match auth::token(authorization, cookie) {
+            Some(token) if with(|auth| auth.is_valid(token, peer.0)) => {
+                Ok(Authorized(Token::try_from(token).unwrap()))
+            }
+            _ => Err(api::reject(robodog_core::api::ApiError::UNAUTHORIZED)),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use core::cell::RefCell;
+use embassy_net::IpAddress;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+const CAPACITY: usize = 8;
+/// Clients that have not been seen for this long are no longer counted
+const EXPIRY: Duration = Duration::from_secs(60);
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(IpAddress, Instant), CAPACITY>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Records network activity of a client
+pub fn seen(address: IpAddress) {
+    let now = Instant::now();
+    CLIENTS.lock(|clients| {
+        let mut clients = clients.borrow_mut();
+        clients.retain(|(_, last)| now - *last < EXPIRY);
+        match clients.iter_mut().find(|(a, _)| *a == address) {
+            Some((_, last)) => *last = now,
+            None => {
+                clients.push((address, now)).ok();
+            }
+        }
+    });
+}
+
+/// Number of clients seen on the network within the last minute
+pub fn count() -> usize {
+    let now = Instant::now();
+    CLIENTS.lock(|clients| {
+        clients
+            .borrow()
+            .iter()
+            .filter(|(_, last)| now - *last < EXPIRY)
+            .count()
+    })
+}
+
+/// Keeps counting a client with an open connection, runs until dropped
+pub async fn connected(address: IpAddress) -> ! {
+    loop {
+        seen(address);
+        Timer::after(EXPIRY / 2).await;
+    }
+}
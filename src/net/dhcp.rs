@@ -0,0 +1,321 @@
+use crate::net::clients;
+use defmt::{Debug2Format, debug, info, warn};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant};
+use robodog_core::settings::{self, AccessPoint};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+/// Upper bound for [Pool::size]
+const MAX_LEASES: usize = settings::MAX_LEASES as usize;
+/// Minimum size a DHCP client has to accept
+const MAX_PACKET_SIZE: usize = 576;
+
+const BOOT_REQUEST: u8 = 1;
+const BOOT_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVER: u8 = 6;
+const OPT_REQUESTED_ADDRESS: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const DECLINE: u8 = 4;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+
+/// Addresses handed out to clients of the access point
+#[derive(Copy, Clone, Debug)]
+pub struct Pool {
+    /// Address of the robot, handed out as router and DNS server
+    pub server: Ipv4Address,
+    pub prefix_len: u8,
+    /// First address of the pool, the following `size - 1` addresses are leased as well
+    pub first: Ipv4Address,
+    /// Number of addresses, capped at [MAX_LEASES]
+    pub size: u8,
+    pub lease_time: Duration,
+}
+
+impl Pool {
+    /// Pool of the access point settings, which are validated to lie in its subnet
+    pub fn new(ap: &AccessPoint) -> Self {
+        Pool {
+            server: Ipv4Address::from(ap.address),
+            prefix_len: ap.prefix_len,
+            first: Ipv4Address::from(ap.pool_start),
+            size: ap.pool_size,
+            lease_time: Duration::from_secs(ap.lease_time_s.into()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.size as usize).min(MAX_LEASES)
+    }
+
+    fn address(&self, index: usize) -> Ipv4Address {
+        Ipv4Address::from(u32::from(self.first) + index as u32)
+    }
+
+    fn index(&self, address: Ipv4Address) -> Option<usize> {
+        let index = u32::from(address).checked_sub(u32::from(self.first))? as usize;
+        (index < self.len()).then_some(index)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Lease {
+    mac: [u8; 6],
+    expires: Instant,
+}
+
+struct Leases {
+    pool: Pool,
+    leases: [Option<Lease>; MAX_LEASES],
+}
+
+impl Leases {
+    fn new(pool: Pool) -> Self {
+        Leases {
+            pool,
+            leases: [None; MAX_LEASES],
+        }
+    }
+
+    fn is_free(&self, index: usize, mac: [u8; 6], now: Instant) -> bool {
+        if self.pool.address(index) == self.pool.server {
+            return false;
+        }
+        match self.leases[index] {
+            None => true,
+            Some(lease) => lease.mac == mac || lease.expires <= now,
+        }
+    }
+
+    /// The current address of the client, the requested one if free, or any free address
+    fn select(&self, mac: [u8; 6], requested: Option<Ipv4Address>, now: Instant) -> Option<usize> {
+        let current = (0..self.pool.len())
+            .find(|&i| matches!(self.leases[i], Some(lease) if lease.mac == mac));
+        let requested = requested
+            .and_then(|address| self.pool.index(address))
+            .filter(|&i| self.is_free(i, mac, now));
+        current
+            .or(requested)
+            .or_else(|| (0..self.pool.len()).find(|&i| self.is_free(i, mac, now)))
+    }
+
+    fn assign(&mut self, index: usize, mac: [u8; 6], now: Instant) {
+        for lease in self.leases.iter_mut() {
+            if matches!(lease, Some(lease) if lease.mac == mac) {
+                *lease = None;
+            }
+        }
+        self.leases[index] = Some(Lease {
+            mac,
+            expires: now + self.pool.lease_time,
+        });
+    }
+
+    fn release(&mut self, mac: [u8; 6]) {
+        for lease in self.leases.iter_mut() {
+            if matches!(lease, Some(lease) if lease.mac == mac) {
+                *lease = None;
+            }
+        }
+    }
+}
+
+struct Request {
+    kind: u8,
+    xid: [u8; 4],
+    flags: [u8; 2],
+    client_address: Ipv4Address,
+    chaddr: [u8; 16],
+    requested: Option<Ipv4Address>,
+    server_id: Option<Ipv4Address>,
+}
+
+impl Request {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < OPTIONS_OFFSET
+            || buf[0] != BOOT_REQUEST
+            || buf[1] != HTYPE_ETHERNET
+            || buf[2] != 6
+            || buf[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut request = Request {
+            kind: 0,
+            xid: buf[4..8].try_into().unwrap(),
+            flags: buf[10..12].try_into().unwrap(),
+            client_address: address(&buf[12..16])?,
+            chaddr: buf[28..44].try_into().unwrap(),
+            requested: None,
+            server_id: None,
+        };
+
+        let mut i = OPTIONS_OFFSET;
+        while i < buf.len() {
+            let code = buf[i];
+            if code == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            if code == OPT_END {
+                break;
+            }
+            let len = *buf.get(i + 1)? as usize;
+            let data = buf.get(i + 2..i + 2 + len)?;
+            match code {
+                OPT_MESSAGE_TYPE => request.kind = *data.first()?,
+                OPT_REQUESTED_ADDRESS => request.requested = address(data),
+                OPT_SERVER_ID => request.server_id = address(data),
+                _ => {}
+            }
+            i += 2 + len;
+        }
+        (request.kind != 0).then_some(request)
+    }
+
+    fn mac(&self) -> [u8; 6] {
+        self.chaddr[..6].try_into().unwrap()
+    }
+
+    /// Builds the reply into `buf` and returns its length
+    fn reply(&self, kind: u8, address: Ipv4Address, pool: &Pool, buf: &mut [u8]) -> usize {
+        buf.fill(0);
+        buf[0] = BOOT_REPLY;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&self.xid);
+        buf[10..12].copy_from_slice(&self.flags);
+        buf[16..20].copy_from_slice(&address.octets());
+        buf[20..24].copy_from_slice(&pool.server.octets());
+        buf[28..44].copy_from_slice(&self.chaddr);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut len = OPTIONS_OFFSET;
+        let mut option = |code: u8, data: &[u8]| {
+            buf[len] = code;
+            buf[len + 1] = data.len() as u8;
+            buf[len + 2..len + 2 + data.len()].copy_from_slice(data);
+            len += 2 + data.len();
+        };
+        option(OPT_MESSAGE_TYPE, &[kind]);
+        option(OPT_SERVER_ID, &pool.server.octets());
+        if kind != NAK {
+            let lease_secs = pool.lease_time.as_secs() as u32;
+            let netmask = u32::MAX << (32 - pool.prefix_len);
+            option(OPT_LEASE_TIME, &lease_secs.to_be_bytes());
+            option(OPT_SUBNET_MASK, &netmask.to_be_bytes());
+            option(OPT_ROUTER, &pool.server.octets());
+            // see crate::net::dns
+            option(OPT_DNS_SERVER, &pool.server.octets());
+        }
+        buf[len] = OPT_END;
+        len + 1
+    }
+}
+
+fn address(data: &[u8]) -> Option<Ipv4Address> {
+    let octets: [u8; 4] = data.try_into().ok()?;
+    Some(Ipv4Address::from(octets))
+}
+
+/// Leases addresses from `pool` to clients of the access point, only spawned in AP mode
+#[embassy_executor::task]
+pub async fn dhcp_task(stack: Stack<'static>, pool: Pool) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(SERVER_PORT).expect("dhcp bind failed");
+    info!(
+        "DHCP server leasing {} addresses from {}",
+        pool.len(),
+        pool.first
+    );
+
+    let mut leases = Leases::new(pool);
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut buf).await else {
+            warn!("DHCP packet truncated");
+            continue;
+        };
+        let Some(request) = Request::parse(&buf[..len]) else {
+            continue;
+        };
+        if request.server_id.is_some_and(|id| id != pool.server) {
+            // the client picked another server
+            continue;
+        }
+
+        let now = Instant::now();
+        let mac = request.mac();
+        let (kind, address) = match request.kind {
+            DISCOVER => match leases.select(mac, request.requested, now) {
+                Some(index) => (OFFER, pool.address(index)),
+                None => {
+                    warn!("DHCP pool exhausted");
+                    continue;
+                }
+            },
+            REQUEST => {
+                let wanted = request.requested.unwrap_or(request.client_address);
+                match pool.index(wanted).filter(|&i| leases.is_free(i, mac, now)) {
+                    Some(index) => {
+                        leases.assign(index, mac, now);
+                        clients::seen(IpAddress::Ipv4(wanted));
+                        info!("DHCP lease {} to {=[u8]:x}", wanted, mac[..]);
+                        (ACK, wanted)
+                    }
+                    None => (NAK, Ipv4Address::UNSPECIFIED),
+                }
+            }
+            DECLINE | RELEASE => {
+                leases.release(mac);
+                continue;
+            }
+            _ => continue,
+        };
+
+        let len = request.reply(kind, address, &pool, &mut buf);
+        // clients without an address can only be reached by broadcast
+        let destination = if request.client_address.is_unspecified() || kind == NAK {
+            Ipv4Address::BROADCAST
+        } else {
+            request.client_address
+        };
+        if let Err(err) = socket
+            .send_to(
+                &buf[..len],
+                IpEndpoint::new(destination.into(), CLIENT_PORT),
+            )
+            .await
+        {
+            debug!("DHCP reply failed: {}", Debug2Format(&err));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use defmt::{Debug2Format, debug, info};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Stack};
+
+const PORT: u16 = 53;
+const MAX_PACKET_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const TTL: u32 = 60;
+/// Pointer to the name of the first question
+const NAME_POINTER: [u8; 2] = [0xC0, HEADER_SIZE as u8];
+/// Name pointer, type, class, TTL, data length and the address
+const ANSWER_SIZE: usize = 16;
+
+/// Position after the name of the first question
+fn skip_name(buf: &[u8]) -> Option<usize> {
+    let mut pos = HEADER_SIZE;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += 1 + len;
+    }
+}
+
+/// Turns the query in `buf` into a response, returns its length or `None` to ignore it
+fn answer(buf: &mut [u8], len: usize, address: Ipv4Address) -> Option<usize> {
+    // anyone on the network can send anything, nothing is indexed before the length is known
+    if len < HEADER_SIZE {
+        return None;
+    }
+    let query = buf.get(..len)?;
+    let flags = u16::from_be_bytes(query.get(2..4)?.try_into().ok()?);
+    let questions = u16::from_be_bytes(query.get(4..6)?.try_into().ok()?);
+    // only standard queries with one question
+    if flags & 0xF800 != 0 || questions != 1 {
+        return None;
+    }
+    let name_end = skip_name(query)?;
+    let question_end = name_end + 4;
+    let kind = u16::from_be_bytes(query.get(name_end..name_end + 2)?.try_into().ok()?);
+    let class = u16::from_be_bytes(query.get(name_end + 2..question_end)?.try_into().ok()?);
+
+    // response, authoritative, recursion desired copied from the query
+    let flags = 0x8400 | (flags & 0x0100);
+    buf[2..4].copy_from_slice(&flags.to_be_bytes());
+    // no authority or additional records, even if the query had some
+    buf[8..12].fill(0);
+
+    // other record types get an empty answer so clients fall back to the A record
+    if kind != TYPE_A || class != CLASS_IN {
+        buf[6..8].fill(0);
+        return Some(question_end);
+    }
+
+    if question_end + ANSWER_SIZE > buf.len() {
+        return None;
+    }
+    buf[6..8].copy_from_slice(&1u16.to_be_bytes());
+    let mut pos = question_end;
+    let mut put = |data: &[u8]| {
+        buf[pos..pos + data.len()].copy_from_slice(data);
+        pos += data.len();
+    };
+    put(&NAME_POINTER);
+    put(&TYPE_A.to_be_bytes());
+    put(&CLASS_IN.to_be_bytes());
+    put(&TTL.to_be_bytes());
+    put(&4u16.to_be_bytes());
+    put(&address.octets());
+    Some(pos)
+}
+
+/// Captive portal DNS, resolves every name to the robot so phones open the UI on connect.
+/// Only spawned in AP mode.
+#[embassy_executor::task]
+pub async fn dns_task(stack: Stack<'static>, address: Ipv4Address) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(PORT).expect("dns bind failed");
+    info!("Captive portal DNS resolving everything to {}", address);
+
+    // room for the answer after a query of maximum size
+    let mut buf = [0; MAX_PACKET_SIZE + 16];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut buf[..MAX_PACKET_SIZE]).await else {
+            continue;
+        };
+        let Some(len) = answer(&mut buf, len, address) else {
+            continue;
+        };
+        if let Err(err) = socket.send_to(&buf[..len], meta.endpoint).await {
+            debug!("DNS reply failed: {}", Debug2Format(&err));
+        }
+    }
+}
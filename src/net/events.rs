@@ -0,0 +1,72 @@
+use crate::control::status::MOTION_EVENTS;
+use crate::control::telemetry::{self, Telemetry};
+use crate::net::clients;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+use picoserve::response::{EventSource, EventWriter};
+use serde::Serialize;
+
+pub const DEFAULT_INTERVAL_MS: u64 = 200;
+const MIN_INTERVAL_MS: u64 = 20;
+const MAX_INTERVAL_MS: u64 = 5000;
+
+#[derive(Serialize)]
+struct TelemetryEvent {
+    telemetry: Telemetry,
+    /// Clients seen on the network within the last minute
+    clients: usize,
+    uptime_ms: u64,
+}
+
+/// Streams telemetry at a fixed rate and motion events as they happen
+pub struct TelemetryEvents {
+    interval: Duration,
+}
+
+impl TelemetryEvents {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval: Duration::from_millis(interval_ms.clamp(MIN_INTERVAL_MS, MAX_INTERVAL_MS)),
+        }
+    }
+}
+
+impl EventSource for TelemetryEvents {
+    async fn write_events<W: Write>(self, mut writer: EventWriter<'_, W>) -> Result<(), W::Error> {
+        let mut buffer = [0; 1024];
+        // motion events are optional, all subscriber slots may be taken
+        let mut subscriber = MOTION_EVENTS.subscriber().ok();
+        let mut next = Instant::now();
+
+        loop {
+            let motion = async {
+                match subscriber.as_mut() {
+                    Some(s) => s.next_message_pure().await,
+                    None => core::future::pending().await,
+                }
+            };
+            match select(Timer::at(next), motion).await {
+                Either::First(()) => {
+                    // skip ticks instead of bursting when the client is slow to read
+                    next = (next + self.interval).max(Instant::now());
+                    let event = TelemetryEvent {
+                        telemetry: telemetry::snapshot(),
+                        clients: clients::count(),
+                        uptime_ms: Instant::now().as_millis(),
+                    };
+                    if let Ok(len) = serde_json_core::to_slice(&event, &mut buffer) {
+                        let data = core::str::from_utf8(&buffer[..len]).unwrap();
+                        writer.write_event("telemetry", data).await?;
+                    }
+                }
+                Either::Second(event) => {
+                    if let Ok(len) = serde_json_core::to_slice(&event, &mut buffer) {
+                        let data = core::str::from_utf8(&buffer[..len]).unwrap();
+                        writer.write_event("motion", data).await?;
+                    }
+                }
+            }
+        }
+    }
+}
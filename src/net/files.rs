@@ -0,0 +1,198 @@
+use crate::assets::{self, AssetError};
+use crate::net::api::{self, ApiError, ApiResult};
+use crate::net::auth::{Authorized, Peer};
+use core::fmt::{self, Display, Write as _};
+use embedded_io_async::{Read, Write};
+use heapless::String;
+use picoserve::ResponseSent;
+use picoserve::extract::FromRequestParts;
+use picoserve::request::{Path, Request};
+use picoserve::response::{Content, IntoResponse, Json, Response, ResponseWriter, StatusCode};
+use picoserve::routing::{PathRouterService, RequestHandlerService};
+use serde::Serialize;
+
+/// Entity tag of a file, its CRC-32
+struct ETag(u32);
+
+impl Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{:08x}\"", self.0)
+    }
+}
+
+impl ETag {
+    /// Whether an `If-None-Match` header lists this tag
+    fn matches(&self, if_none_match: &str) -> bool {
+        let mut tag = String::<10>::new();
+        write!(tag, "{self}").unwrap();
+        if_none_match
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == tag || t == "*")
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows gzip. Codings with `q=0` are refused, and an
+/// explicit `gzip` entry takes precedence over `*`.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut wildcard = false;
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let refused = params.any(|p| {
+            let p = p.trim();
+            (p.starts_with("q=") || p.starts_with("Q=")) && p[2..].parse() == Ok(0.0)
+        });
+        if coding.eq_ignore_ascii_case("gzip") {
+            return !refused;
+        }
+        if coding == "*" {
+            wildcard = !refused;
+        }
+    }
+    wildcard
+}
+
+struct Body(assets::File);
+
+impl Content for Body {
+    fn content_type(&self) -> &'static str {
+        self.0.content_type
+    }
+
+    fn content_length(&self) -> usize {
+        self.0.data.len()
+    }
+
+    async fn write_content<W: Write>(self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0.data).await
+    }
+}
+
+/// Serves the files of the asset bundles, see [`assets::web`]. Used as the fallback of the
+/// router, so anything not matched by a route ends up here.
+///
+/// Files are sent gzip compressed if the client accepts that, and revalidated on every load
+/// with their ETag, so unchanged files cost a `304 Not Modified`.
+pub struct StaticFiles;
+
+impl<State, PathParameters> PathRouterService<State, PathParameters> for StaticFiles {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        _state: &State,
+        _path_parameters: PathParameters,
+        path: Path<'_>,
+        mut request: Request<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let headers = request.parts.headers();
+        let header = |name| headers.get(name).and_then(|v| v.as_str().ok());
+        let gzip = header("Accept-Encoding").is_some_and(accepts_gzip);
+        let if_none_match = header("If-None-Match");
+
+        let file = path
+            .encoded()
+            .strip_prefix('/')
+            .and_then(|p| assets::web(p, gzip));
+        let method = request.parts.method();
+        let connection = request.body_connection.finalize().await?;
+        let Some(file) = file else {
+            return StatusCode::NOT_FOUND
+                .write_to(connection, response_writer)
+                .await;
+        };
+        if method != "GET" && method != "HEAD" {
+            return StatusCode::METHOD_NOT_ALLOWED
+                .write_to(connection, response_writer)
+                .await;
+        }
+
+        let etag = ETag(file.crc);
+        let compressed = file.gzip;
+        if if_none_match.is_some_and(|v| etag.matches(v)) {
+            return Response::new(StatusCode::NOT_MODIFIED, "")
+                .with_header("ETag", etag)
+                .write_to(connection, response_writer)
+                .await;
+        }
+        let response = Response::new(StatusCode::OK, Body(file))
+            .with_header("ETag", etag)
+            .with_header("Cache-Control", "no-cache")
+            .with_header("Vary", "Accept-Encoding");
+        if compressed {
+            response
+                .with_header("Content-Encoding", "gzip")
+                .write_to(connection, response_writer)
+                .await
+        } else {
+            response.write_to(connection, response_writer).await
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Stored {
+    pub files: u16,
+}
+
+fn asset_error(err: AssetError) -> ApiError {
+    match err {
+        AssetError::Busy => api::error(StatusCode::CONFLICT, "another upload is running"),
+        AssetError::InUse => api::error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "assets are being sent, try again",
+        ),
+        AssetError::Invalid => api::error(StatusCode::BAD_REQUEST, "invalid asset bundle"),
+        AssetError::TooLarge => api::error(StatusCode::PAYLOAD_TOO_LARGE, "bundle too large"),
+        AssetError::Checksum => api::error(StatusCode::BAD_REQUEST, "checksum mismatch"),
+        AssetError::Flash => api::error(StatusCode::INTERNAL_SERVER_ERROR, "writing flash failed"),
+    }
+}
+
+async fn upload<R: Read>(mut reader: R) -> Result<ApiResult<Stored>, R::Error> {
+    let mut upload = match assets::begin() {
+        Ok(upload) => upload,
+        Err(err) => return Ok(Err(asset_error(err))),
+    };
+
+    let mut buf = [0; 512];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        if let Err(err) = upload.write(&buf[..n]) {
+            return Ok(Err(asset_error(err)));
+        }
+    }
+
+    Ok(match upload.finish() {
+        Ok(files) => Ok((StatusCode::OK, Json(Stored { files }))),
+        Err(err) => Err(asset_error(err)),
+    })
+}
+
+/// `POST /api/v1/assets` with a bundle built by `tools/asset-bundle` as body
+pub struct AssetUpload;
+
+impl RequestHandlerService<Peer, ()> for AssetUpload {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        peer: &Peer,
+        (): (),
+        mut request: Request<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let result = match Authorized::from_request_parts(peer, &request.parts).await {
+            Ok(_) => upload(request.body_connection.body().reader()).await?,
+            Err(rejection) => Err(rejection),
+        };
+        let connection = request.body_connection.finalize().await?;
+        result.write_to(connection, response_writer).await
+    }
+}
+
+pub fn clear() -> Result<StatusCode, ApiError> {
+    assets::clear().map_err(asset_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
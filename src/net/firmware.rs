@@ -0,0 +1,80 @@
+use crate::net::api::{self, ApiResult};
+use crate::net::auth::{Authorized, Peer};
+use crate::ota::{self, UpdateError};
+use crate::peripheral::watchdog;
+use embedded_io_async::Read;
+use picoserve::ResponseSent;
+use picoserve::extract::FromRequestParts;
+use picoserve::request::Request;
+use picoserve::response::{IntoResponse, Json, ResponseWriter, StatusCode};
+use picoserve::routing::RequestHandlerService;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Uploaded {
+    pub size: u32,
+}
+
+fn update_error(err: UpdateError) -> api::ApiError {
+    match err {
+        UpdateError::Busy => api::error(StatusCode::CONFLICT, "another upload is running"),
+        UpdateError::NotConfirmed => api::error(
+            StatusCode::CONFLICT,
+            "running firmware is not confirmed yet",
+        ),
+        UpdateError::TooLarge => api::error(StatusCode::PAYLOAD_TOO_LARGE, "image too large"),
+        UpdateError::Invalid => api::error(StatusCode::BAD_REQUEST, "not a firmware image"),
+        UpdateError::Checksum => api::error(StatusCode::BAD_REQUEST, "checksum mismatch"),
+        UpdateError::Flash => api::error(StatusCode::INTERNAL_SERVER_ERROR, "writing flash failed"),
+    }
+}
+
+/// Streams the body into the inactive slot and reboots into it if it checks out
+async fn upload<R: Read>(
+    expected_crc: u32,
+    mut reader: R,
+) -> Result<ApiResult<Uploaded>, R::Error> {
+    let mut upload = match ota::begin() {
+        Ok(upload) => upload,
+        Err(err) => return Ok(Err(update_error(err))),
+    };
+
+    let mut buf = [0; 512];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        if let Err(err) = upload.write(&buf[..n]) {
+            return Ok(Err(update_error(err)));
+        }
+    }
+
+    Ok(match upload.finish(expected_crc) {
+        Ok(size) => {
+            watchdog::reboot();
+            Ok((StatusCode::ACCEPTED, Json(Uploaded { size })))
+        }
+        Err(err) => Err(update_error(err)),
+    })
+}
+
+/// `POST /api/v1/firmware/<crc32>` with the raw `.bin` image as body
+pub struct FirmwareUpload;
+
+impl RequestHandlerService<Peer, u32> for FirmwareUpload {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        peer: &Peer,
+        expected_crc: u32,
+        mut request: Request<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let result = match Authorized::from_request_parts(peer, &request.parts).await {
+            Ok(_) => upload(expected_crc, request.body_connection.body().reader()).await?,
+            Err(rejection) => Err(rejection),
+        };
+        let connection = request.body_connection.finalize().await?;
+        result.write_to(connection, response_writer).await
+    }
+}
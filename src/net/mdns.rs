@@ -0,0 +1,319 @@
+use core::fmt::Write;
+use defmt::{Debug2Format, debug, info, warn};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use robodog_core::settings::MAX_HOSTNAME_LEN;
+
+const PORT: u16 = 5353;
+pub const GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+/// Ethernet address the [GROUP] is mapped to
+pub const GROUP_MAC: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+const MAX_PACKET_SIZE: usize = 512;
+const HTTP_PORT: u16 = 80;
+
+const SERVICE: &str = "_http._tcp.local";
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records only this host answers for
+const CACHE_FLUSH: u16 = 0x8000;
+
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+/// Name as dotted, lowercase string
+type Name = String<128>;
+
+struct Names {
+    /// `<hostname>.local`
+    host: Name,
+    /// `<hostname>._http._tcp.local`
+    instance: Name,
+}
+
+impl Names {
+    fn new(hostname: &str) -> Self {
+        let mut host = Name::new();
+        let mut instance = Name::new();
+        write!(host, "{hostname}.local").ok();
+        write!(instance, "{hostname}.{SERVICE}").ok();
+        host.make_ascii_lowercase();
+        instance.make_ascii_lowercase();
+        Names { host, instance }
+    }
+}
+
+/// Records to answer with, answers first, the rest goes to the additional section
+#[derive(Copy, Clone, Default)]
+struct Records {
+    address: bool,
+    services: bool,
+    pointer: bool,
+    service: bool,
+}
+
+impl Records {
+    fn any(&self) -> bool {
+        self.address || self.services || self.pointer || self.service
+    }
+
+    fn or(self, other: Records) -> Records {
+        Records {
+            address: self.address || other.address,
+            services: self.services || other.services,
+            pointer: self.pointer || other.pointer,
+            service: self.service || other.service,
+        }
+    }
+
+    /// Everything announced on startup
+    fn all() -> Records {
+        Records {
+            address: true,
+            services: false,
+            pointer: true,
+            service: true,
+        }
+    }
+}
+
+/// Reads a possibly compressed name starting at `pos`, returns it and the position after it
+fn read_name(buf: &[u8], mut pos: usize) -> Option<(Name, usize)> {
+    let mut name = Name::new();
+    let mut end = None;
+    // bound the number of jumps so malicious pointer loops terminate
+    for _ in 0..32 {
+        let len = *buf.get(pos)? as usize;
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            let target = ((len & 0x3F) << 8) | *buf.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = core::str::from_utf8(buf.get(pos + 1..pos + 1 + len)?).ok()?;
+        if !name.is_empty() {
+            name.push('.').ok()?;
+        }
+        name.push_str(label).ok()?;
+        pos += 1 + len;
+    }
+    None
+}
+
+/// Records matching the questions of a query and the end of its question section, `None` for
+/// anything that is not a query
+fn parse_query(buf: &[u8], names: &Names) -> Option<(Records, usize)> {
+    let flags = u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?);
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let questions = u16::from_be_bytes(buf.get(4..6)?.try_into().ok()?);
+
+    let mut records = Records::default();
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (mut name, next) = read_name(buf, pos)?;
+        let kind = u16::from_be_bytes(buf.get(next..next + 2)?.try_into().ok()?);
+        pos = next + 4;
+
+        name.make_ascii_lowercase();
+        let any = kind == TYPE_ANY;
+        let matched = if name == names.host {
+            Records {
+                address: kind == TYPE_A || any,
+                ..Records::default()
+            }
+        } else if name == SERVICE {
+            Records {
+                pointer: kind == TYPE_PTR || any,
+                ..Records::default()
+            }
+        } else if name == names.instance {
+            Records {
+                service: kind == TYPE_SRV || kind == TYPE_TXT || any,
+                ..Records::default()
+            }
+        } else if name == SERVICES {
+            Records {
+                services: kind == TYPE_PTR || any,
+                ..Records::default()
+            }
+        } else {
+            Records::default()
+        };
+        records = records.or(matched);
+    }
+    Some((records, pos))
+}
+
+struct Response<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Response<'a> {
+    fn new(buf: &'a mut [u8], id: u16) -> Self {
+        buf[..12].fill(0);
+        buf[0..2].copy_from_slice(&id.to_be_bytes());
+        // response, authoritative
+        buf[2..4].copy_from_slice(&0x8400u16.to_be_bytes());
+        Response { buf, len: 12 }
+    }
+
+    /// Reply to the query in `buf` that repeats its question section, which ends at `end`. The
+    /// questions stay where they are, so compressed names in them still point to the right place.
+    fn reply(buf: &'a mut [u8], end: usize) -> Self {
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        let questions = u16::from_be_bytes([buf[4], buf[5]]);
+        let mut response = Response::new(buf, id);
+        response.buf[4..6].copy_from_slice(&questions.to_be_bytes());
+        response.len = end;
+        response
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    fn put_name(&mut self, name: &str) {
+        for label in name.split('.') {
+            self.put(&[label.len() as u8]);
+            self.put(label.as_bytes());
+        }
+        self.put(&[0]);
+    }
+
+    /// Starts a record and returns the position of its data length
+    fn record(&mut self, name: &str, kind: u16, class: u16, ttl: u32) -> usize {
+        self.put_name(name);
+        self.put(&kind.to_be_bytes());
+        self.put(&class.to_be_bytes());
+        self.put(&ttl.to_be_bytes());
+        self.put(&[0, 0]);
+        self.len
+    }
+
+    fn finish_record(&mut self, start: usize) {
+        let len = (self.len - start) as u16;
+        self.buf[start - 2..start].copy_from_slice(&len.to_be_bytes());
+    }
+
+    fn write(&mut self, records: Records, names: &Names, address: Ipv4Address) -> u16 {
+        let mut count = 0;
+        if records.address {
+            let start = self.record(&names.host, TYPE_A, CLASS_IN | CACHE_FLUSH, HOST_TTL);
+            self.put(&address.octets());
+            self.finish_record(start);
+            count += 1;
+        }
+        if records.services {
+            let start = self.record(SERVICES, TYPE_PTR, CLASS_IN, SERVICE_TTL);
+            self.put_name(SERVICE);
+            self.finish_record(start);
+            count += 1;
+        }
+        if records.pointer {
+            let start = self.record(SERVICE, TYPE_PTR, CLASS_IN, SERVICE_TTL);
+            self.put_name(&names.instance);
+            self.finish_record(start);
+            count += 1;
+        }
+        if records.service {
+            let class = CLASS_IN | CACHE_FLUSH;
+            let start = self.record(&names.instance, TYPE_SRV, class, HOST_TTL);
+            // priority, weight
+            self.put(&[0, 0, 0, 0]);
+            self.put(&HTTP_PORT.to_be_bytes());
+            self.put_name(&names.host);
+            self.finish_record(start);
+
+            let start = self.record(&names.instance, TYPE_TXT, class, SERVICE_TTL);
+            let path = "path=/index.html";
+            self.put(&[path.len() as u8]);
+            self.put(path.as_bytes());
+            self.finish_record(start);
+            count += 2;
+        }
+        count
+    }
+
+    /// Writes the answers, records a client will need next go to the additional section
+    fn finish(mut self, answers: Records, names: &Names, address: Ipv4Address) -> usize {
+        let additional = Records {
+            address: !answers.address && (answers.pointer || answers.service),
+            service: !answers.service && answers.pointer,
+            ..Records::default()
+        };
+        let answer_count = self.write(answers, names, address);
+        let additional_count = self.write(additional, names, address);
+        self.buf[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        self.buf[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        self.len
+    }
+}
+
+/// Answers `<hostname>.local` and advertises the web UI as `_http._tcp` service
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>, hostname: String<MAX_HOSTNAME_LEN>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * MAX_PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(PORT).expect("mdns bind failed");
+    if let Err(err) = stack.join_multicast_group(GROUP) {
+        warn!("Joining mDNS group failed: {}", Debug2Format(&err));
+    }
+
+    let names = Names::new(&hostname);
+    let address = stack.config_v4().unwrap().address.address();
+    let group = IpEndpoint::new(GROUP.into(), PORT);
+    info!("mDNS responding as {}", names.host.as_str());
+
+    // room for the answers behind the questions of a query, which legacy replies repeat
+    let mut buf = [0; 2 * MAX_PACKET_SIZE];
+    // announce twice, a second apart, so caches pick up a changed address
+    for _ in 0..2 {
+        let len = Response::new(&mut buf, 0).finish(Records::all(), &names, address);
+        socket.send_to(&buf[..len], group).await.ok();
+        Timer::after(Duration::from_secs(1)).await;
+    }
+
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut buf[..MAX_PACKET_SIZE]).await else {
+            continue;
+        };
+        let Some((records, end)) = parse_query(&buf[..len], &names).filter(|(r, _)| r.any()) else {
+            continue;
+        };
+
+        // legacy resolvers query from another port and expect a unicast reply with their id
+        // and questions, see RFC 6762 section 6.7
+        let (response, destination) = if meta.endpoint.port == PORT {
+            (Response::new(&mut buf, 0), group)
+        } else {
+            (Response::reply(&mut buf, end), meta.endpoint)
+        };
+        let len = response.finish(records, &names, address);
+        if let Err(err) = socket.send_to(&buf[..len], destination).await {
+            debug!("mDNS reply failed: {}", Debug2Format(&err));
+        }
+    }
+}
@@ -1,2 +1,13 @@
+pub mod api;
 pub mod app;
+pub mod auth;
+pub mod clients;
+pub mod dhcp;
+pub mod dns;
+pub mod events;
+pub mod files;
+pub mod firmware;
+pub mod mdns;
 pub mod network;
+pub mod udp;
+pub mod ws;
@@ -1,14 +1,89 @@
 use crate::WEB_TASK_POOL_SIZE;
+use crate::peripheral::watchdog::{self, Supervised};
+use core::cell::Cell;
+use core::fmt::Write;
+use core::task::Context;
 use cyw43::NetDriver;
 use defmt::info;
-use embassy_net::{Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_futures::select::{Either, select};
+use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState};
+use embassy_net::{
+    ConfigV4, DhcpConfig, Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4,
+};
 use embassy_rp::clocks::RoscRng;
-use heapless::Vec;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::once_lock::OnceLock;
+use embassy_time::{Duration, Ticker, with_timeout};
+use heapless::{String, Vec};
+use robodog_core::settings::{DEFAULT_AP_ADDRESS, DEFAULT_AP_PREFIX_LEN};
 use static_cell::StaticCell;
 
+pub use robodog_core::api::{NetworkInfo, WifiMode};
+
 #[embassy_executor::task]
-pub async fn net_task(mut runner: Runner<'static, NetDriver<'static>>) -> ! {
-    runner.run().await
+pub async fn net_task(mut runner: Runner<'static, WatchedDriver<NetDriver<'static>>>) -> ! {
+    // the select polls the runner on every tick as well, so an idle stack still checks in
+    let mut ticker = Ticker::every(watchdog::FEED_INTERVAL);
+    let poll = async {
+        loop {
+            ticker.next().await;
+        }
+    };
+    match select(runner.run(), poll).await {
+        Either::First(never) | Either::Second(never) => never,
+    }
+}
+
+/// Checks in for [Supervised::Net] each time the stack has been polled, which asks for the
+/// link state after handling every received and queued packet
+pub struct WatchedDriver<D>(D);
+
+impl<D: Driver> Driver for WatchedDriver<D> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.0.receive(cx)
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.0.transmit(cx)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        watchdog::check_in(Supervised::Net);
+        self.0.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.0.hardware_address()
+    }
+}
+
+static INFO: Mutex<CriticalSectionRawMutex, Cell<Option<NetworkInfo>>> =
+    Mutex::new(Cell::new(None));
+
+/// Mode and address once the network is up
+pub fn info() -> Option<NetworkInfo> {
+    INFO.lock(Cell::get)
+}
+
+static INDEX_URL: OnceLock<String<40>> = OnceLock::new();
+
+/// Absolute URL of the web UI, relative until the network is up
+pub fn index_url() -> &'static str {
+    INDEX_URL.try_get().map_or("/index.html", String::as_str)
 }
 
 pub struct Network<'d> {
@@ -16,20 +91,22 @@ pub struct Network<'d> {
 }
 
 impl<'d> Network<'d> {
-    pub fn new(net_device: NetDriver<'d>) -> (Network<'d>, Runner<'d, NetDriver<'d>>) {
-        let config = embassy_net::Config::ipv4_static(StaticConfigV4 {
-            address: Ipv4Cidr::new(Ipv4Address::new(169, 254, 1, 1), 16),
-            gateway: None,
-            dns_servers: Vec::<Ipv4Address, 3>::new(),
-        });
+    pub fn new(
+        net_device: NetDriver<'d>,
+    ) -> (Network<'d>, Runner<'d, WatchedDriver<NetDriver<'d>>>) {
+        let config = embassy_net::Config::ipv4_static(Self::static_config(
+            Ipv4Address::from(DEFAULT_AP_ADDRESS),
+            DEFAULT_AP_PREFIX_LEN,
+        ));
 
         let mut rng = RoscRng;
         let seed = rng.next_u64();
 
-        const SIZE: usize = WEB_TASK_POOL_SIZE + 1;
+        // web tasks, udp control, dhcp server, dns server, mdns and one spare
+        const SIZE: usize = WEB_TASK_POOL_SIZE + 5;
         static RESOURCES: StaticCell<StackResources<SIZE>> = StaticCell::new();
         let (stack, runner) = embassy_net::new(
-            net_device,
+            WatchedDriver(net_device),
             config,
             RESOURCES.init(StackResources::<SIZE>::new()),
             seed,
@@ -37,8 +114,44 @@ impl<'d> Network<'d> {
         (Self { stack }, runner)
     }
 
-    pub async fn up(&mut self) {
+    fn static_config(address: Ipv4Address, prefix_len: u8) -> StaticConfigV4 {
+        StaticConfigV4 {
+            address: Ipv4Cidr::new(address, prefix_len),
+            gateway: None,
+            dns_servers: Vec::<Ipv4Address, 3>::new(),
+        }
+    }
+
+    pub fn use_static(&mut self, address: Ipv4Address, prefix_len: u8) {
+        self.stack
+            .set_config_v4(ConfigV4::Static(Self::static_config(address, prefix_len)));
+    }
+
+    pub fn use_dhcp(&mut self, hostname: &str) {
+        let mut config = DhcpConfig::default();
+        config.hostname = String::try_from(hostname).ok();
+        self.stack.set_config_v4(ConfigV4::Dhcp(config));
+    }
+
+    pub async fn up(&mut self, mode: WifiMode) {
         info!("waiting for config to be up...");
         self.stack.wait_config_up().await;
+
+        let address = self.stack.config_v4().unwrap().address.address();
+        info!("network up in {} mode at {}", mode, address);
+        let mut url = String::new();
+        write!(url, "http://{}/index.html", address).ok();
+        INDEX_URL.init(url).ok();
+        INFO.lock(|info| {
+            info.set(Some(NetworkInfo {
+                mode,
+                address: address.octets(),
+            }))
+        });
+    }
+
+    /// Like [Network::up], returns false if the config is not up in time
+    pub async fn up_within(&mut self, mode: WifiMode, timeout: Duration) -> bool {
+        with_timeout(timeout, self.up(mode)).await.is_ok()
     }
 }
@@ -0,0 +1,128 @@
+use crate::control::command::{self, ServoTask};
+use crate::control::status;
+use crate::control::teleop::Teleop;
+use crate::net::{auth, clients};
+use defmt::{Debug2Format, debug, info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{Duration, Instant, Ticker};
+use robodog_core::protocol::{MAX_PACKET_SIZE, Message, PORT, Packet, SequenceFilter, StateReply};
+
+/// How often the state is sent to the client and the velocity is integrated
+const STATE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Low latency teleoperation, see [robodog_core::protocol]. Only the last client that sent a
+/// packet is controlling and receives state replies. Packets are only accepted from addresses
+/// holding a session of the web interface, except stops.
+#[embassy_executor::task]
+pub async fn udp_task(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(PORT).expect("udp bind failed");
+    info!("UDP control listening on port {}", PORT);
+
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut client: Option<IpEndpoint> = None;
+    let mut filter = SequenceFilter::new();
+    let mut teleop = Teleop::new();
+    let mut reply_seq = 0u32;
+    // a ticker keeps its schedule while packets arrive faster than the interval
+    let mut ticker = Ticker::every(STATE_INTERVAL);
+    let mut last_tick = Instant::now();
+
+    loop {
+        match select(socket.recv_from(&mut buf), ticker.next()).await {
+            Either::First(Ok((len, meta))) => {
+                // only a valid packet may take over control
+                let packet = match Packet::decode(&buf[..len]) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        debug!(
+                            "Invalid packet from {}: {}",
+                            meta.endpoint,
+                            Debug2Format(&err)
+                        );
+                        continue;
+                    }
+                };
+                clients::seen(meta.endpoint.addr);
+                if !auth::is_authorized(meta.endpoint.addr) {
+                    // stopping is always allowed, like on `/stop`
+                    if packet.message == Message::Stop {
+                        command::submit(ServoTask::STOP).ok();
+                    } else {
+                        debug!("UDP packet from {} without a session", meta.endpoint);
+                    }
+                    continue;
+                }
+                if client != Some(meta.endpoint) {
+                    info!("UDP client: {}", meta.endpoint);
+                    client = Some(meta.endpoint);
+                    filter.reset();
+                    teleop = Teleop::new();
+                }
+
+                if !filter.accept(packet.seq) {
+                    debug!("Dropping stale packet {}", packet.seq);
+                    continue;
+                }
+
+                teleop.received();
+                let result = match packet.message {
+                    Message::Pose(pose) => teleop.set_pose(pose),
+                    Message::Velocity(velocity) => {
+                        teleop.set_velocity(velocity);
+                        Ok(())
+                    }
+                    Message::Stop => {
+                        teleop.stop();
+                        command::submit(ServoTask::STOP).ok();
+                        Ok(())
+                    }
+                    Message::Heartbeat | Message::State(_) => Ok(()),
+                };
+                if let Err(err) = result {
+                    debug!("UDP command {} rejected: {}", packet.seq, err);
+                }
+            }
+            Either::First(Err(_)) => warn!("UDP packet truncated"),
+            Either::Second(()) => {
+                let now = Instant::now();
+                teleop.tick(now - last_tick);
+                last_tick = now;
+
+                // the session ended, the client no longer gets state
+                client = client.filter(|c| auth::is_authorized(c.addr));
+                let Some(client) = client else {
+                    continue;
+                };
+                let status = status::status();
+                reply_seq = reply_seq.wrapping_add(1);
+                let reply = Packet::new(
+                    reply_seq,
+                    Message::State(StateReply {
+                        state: status.state as u8,
+                        estop: status.estop.is_some(),
+                        queued: status.queued as u16,
+                        pose: teleop.pose().unwrap_or_default(),
+                    }),
+                );
+                if let Some(len) = reply.encode(&mut buf)
+                    && socket.send_to(&buf[..len], client).await.is_err()
+                {
+                    debug!("UDP state reply failed");
+                }
+            }
+        }
+    }
+}
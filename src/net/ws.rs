@@ -0,0 +1,86 @@
+use crate::control::status;
+use crate::control::teleop::Teleop;
+use defmt::{debug, warn};
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_io_async::{Read, Write};
+use picoserve::futures::Either;
+use picoserve::response::ws::{Message, SocketRx, SocketTx, WebSocketCallback};
+use robodog_core::api::{ErrorBody, Position, StreamCommand, StreamState};
+
+/// How often the state is pushed to the client and the velocity is integrated
+const STATE_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct StreamHandler;
+
+impl WebSocketCallback for StreamHandler {
+    async fn run<R: Read, W: Write<Error = R::Error>>(
+        self,
+        mut rx: SocketRx<R>,
+        mut tx: SocketTx<W>,
+    ) -> Result<(), W::Error> {
+        let mut buffer = [0; 512];
+        let mut out = [0; 256];
+        let mut teleop = Teleop::new();
+        // a ticker keeps its schedule while messages arrive faster than the interval
+        let mut ticker = Ticker::every(STATE_INTERVAL);
+        let mut last_tick = Instant::now();
+
+        let close_reason = loop {
+            let data = match rx.next_message(&mut buffer, ticker.next()).await? {
+                Either::First(Ok(Message::Text(text))) => text.as_bytes(),
+                Either::First(Ok(Message::Binary(data))) => data,
+                Either::First(Ok(Message::Ping(data))) => {
+                    tx.send_pong(data).await?;
+                    continue;
+                }
+                Either::First(Ok(Message::Pong(_))) => continue,
+                Either::First(Ok(Message::Close(_))) => break None,
+                Either::First(Err(_)) => {
+                    warn!("Websocket read error");
+                    break Some((1002, "Websocket Error"));
+                }
+                Either::Second(()) => {
+                    let now = Instant::now();
+                    teleop.tick(now - last_tick);
+                    last_tick = now;
+
+                    let status = status::status();
+                    let state = StreamState {
+                        state: status.state,
+                        pose: teleop.pose().map(Position::from_array),
+                        queued: status.queued,
+                        estop: status.estop.is_some(),
+                    };
+                    if let Ok(len) = serde_json_core::to_slice(&state, &mut out) {
+                        tx.send_text(core::str::from_utf8(&out[..len]).unwrap())
+                            .await?;
+                    }
+                    continue;
+                }
+            };
+
+            teleop.received();
+            let result = match serde_json_core::from_slice::<StreamCommand>(data) {
+                Ok((cmd, _)) => {
+                    if let Some(velocity) = cmd.velocity {
+                        teleop.set_velocity(velocity.to_array());
+                    }
+                    match cmd.pose {
+                        Some(pose) => teleop.set_pose(pose.to_array()).map_err(|e| e.message()),
+                        None => Ok(()),
+                    }
+                }
+                Err(_) => Err("invalid command"),
+            };
+            if let Err(error) = result {
+                debug!("Stream command rejected: {}", error);
+                if let Ok(len) = serde_json_core::to_slice(&ErrorBody { error }, &mut out) {
+                    tx.send_text(core::str::from_utf8(&out[..len]).unwrap())
+                        .await?;
+                }
+            }
+        };
+
+        tx.close(close_reason).await
+    }
+}
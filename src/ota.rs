@@ -0,0 +1,201 @@
+use crate::crc32::Crc32;
+use crate::net::network;
+use crate::peripheral::flash::{self, ERASE_SIZE, FLASH_BASE, FlashDriver};
+use crate::peripheral::watchdog;
+use defmt::{Format, error, info, warn};
+use embassy_boot_rp::{
+    AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError, State,
+};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_time::{Duration, Timer};
+
+pub use robodog_core::api::FirmwareState;
+
+/// How long a new firmware has to run healthy before it is confirmed
+const CONFIRM_AFTER: Duration = Duration::from_secs(30);
+
+type Partition = BlockingPartition<'static, CriticalSectionRawMutex, FlashDriver>;
+type Updater<'a> = BlockingFirmwareUpdater<'a, Partition, Partition>;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Format)]
+pub enum UpdateError {
+    /// Another upload is running
+    Busy,
+    /// The running firmware is still on trial, confirm or revert it first
+    NotConfirmed,
+    TooLarge,
+    /// Not a firmware image for this board
+    Invalid,
+    /// Received data does not match the given checksum
+    Checksum,
+    Flash,
+}
+
+impl From<FirmwareUpdaterError> for UpdateError {
+    fn from(err: FirmwareUpdaterError) -> Self {
+        match err {
+            FirmwareUpdaterError::BadState => UpdateError::NotConfirmed,
+            _ => UpdateError::Flash,
+        }
+    }
+}
+
+unsafe extern "C" {
+    static __bootloader_dfu_start: u32;
+    static __bootloader_dfu_end: u32;
+}
+
+/// Offset and size of the slot new images are written to, see `memory.x`
+fn dfu() -> (u32, u32) {
+    let start = &raw const __bootloader_dfu_start as u32;
+    let end = &raw const __bootloader_dfu_end as u32;
+    (start, end - start)
+}
+
+fn with_updater<R>(f: impl FnOnce(&mut Updater) -> R) -> R {
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash::shared(), flash::shared());
+    let mut aligned = AlignedBuffer([0; 1]);
+    let mut updater = BlockingFirmwareUpdater::new(config, &mut aligned.0);
+    f(&mut updater)
+}
+
+pub fn state() -> FirmwareState {
+    match with_updater(|updater| updater.get_state()) {
+        Ok(State::Swap) => FirmwareState::Trial,
+        _ => FirmwareState::Confirmed,
+    }
+}
+
+pub struct Upload {
+    len: u32,
+    /// Where the next sector is written, relative to the slot
+    offset: usize,
+    crc: Crc32,
+    /// Collects data until a whole sector can be written
+    buf: [u8; ERASE_SIZE],
+    buffered: usize,
+}
+
+static UPLOAD: Mutex<CriticalSectionRawMutex, Upload> = Mutex::new(Upload {
+    len: 0,
+    offset: 0,
+    crc: Crc32::new(),
+    buf: [0; ERASE_SIZE],
+    buffered: 0,
+});
+
+/// Starts writing a new image to the inactive slot, one upload at a time
+pub fn begin() -> Result<MutexGuard<'static, CriticalSectionRawMutex, Upload>, UpdateError> {
+    let mut upload = UPLOAD.try_lock().map_err(|_| UpdateError::Busy)?;
+    if state() == FirmwareState::Trial {
+        return Err(UpdateError::NotConfirmed);
+    }
+    upload.len = 0;
+    upload.offset = 0;
+    upload.crc = Crc32::new();
+    upload.buffered = 0;
+    info!("Firmware upload started");
+    Ok(upload)
+}
+
+impl Upload {
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), UpdateError> {
+        let (_, slot_size) = dfu();
+        // the DFU slot is one sector larger than the active one, used for swapping
+        if self.len + data.len() as u32 > slot_size - ERASE_SIZE as u32 {
+            return Err(UpdateError::TooLarge);
+        }
+        self.crc.update(data);
+        self.len += data.len() as u32;
+
+        while !data.is_empty() {
+            let n = data.len().min(ERASE_SIZE - self.buffered);
+            self.buf[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered == ERASE_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UpdateError> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        self.buf[self.buffered..].fill(0xFF);
+        with_updater(|updater| updater.write_firmware(self.offset, &self.buf))?;
+        self.offset += ERASE_SIZE;
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Checks the written image and marks it to be swapped in on the next boot
+    pub fn finish(&mut self, expected_crc: u32) -> Result<u32, UpdateError> {
+        self.flush()?;
+        if self.crc.finish() != expected_crc {
+            warn!("Firmware checksum mismatch");
+            return Err(UpdateError::Checksum);
+        }
+
+        // read back what actually ended up in flash
+        let (start, _) = dfu();
+        let mut crc = Crc32::new();
+        let mut offset = 0;
+        while offset < self.len {
+            let n = (self.len - offset).min(ERASE_SIZE as u32) as usize;
+            flash::read(start + offset, &mut self.buf[..n]).map_err(|_| UpdateError::Flash)?;
+            if offset == 0 && !is_image(&self.buf[..n]) {
+                return Err(UpdateError::Invalid);
+            }
+            crc.update(&self.buf[..n]);
+            offset += n as u32;
+        }
+        if crc.finish() != expected_crc {
+            error!("Firmware verification failed");
+            return Err(UpdateError::Flash);
+        }
+
+        with_updater(|updater| updater.mark_updated())?;
+        info!(
+            "Firmware of {} bytes verified, swapping on next boot",
+            self.len
+        );
+        Ok(self.len)
+    }
+}
+
+/// Checks the vector table, the stack has to start in RAM and the reset handler in flash
+fn is_image(data: &[u8]) -> bool {
+    let Some(header) = data.get(..8) else {
+        return false;
+    };
+    let stack = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let reset = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    (0x2000_0000..=0x2008_2000).contains(&stack)
+        && (FLASH_BASE..FLASH_BASE + flash::FLASH_SIZE as u32).contains(&reset)
+}
+
+/// Confirms a new firmware once it has run healthy for a while, otherwise resets so the
+/// bootloader restores the previous one
+#[embassy_executor::task]
+pub async fn confirm_task() {
+    if state() != FirmwareState::Trial {
+        return;
+    }
+    info!("Running new firmware on trial");
+    Timer::after(CONFIRM_AFTER).await;
+
+    if watchdog::healthy() && network::info().is_some() {
+        match with_updater(|updater| updater.mark_booted()) {
+            Ok(()) => info!("New firmware confirmed"),
+            Err(_) => error!("Confirming firmware failed"),
+        }
+    } else {
+        error!("New firmware is unhealthy, reverting");
+        watchdog::reboot();
+    }
+}
@@ -0,0 +1,16 @@
+use crate::control::estop::{self, EStopMode, EStopSource};
+use embassy_rp::gpio::Input;
+use embassy_time::Timer;
+
+/// Physical emergency stop, a normally open button pulling the pin to ground
+#[embassy_executor::task]
+pub async fn estop_button_task(mut button: Input<'static>) -> ! {
+    loop {
+        button.wait_for_low().await;
+        estop::trigger(EStopSource::Button, EStopMode::Relax);
+
+        button.wait_for_high().await;
+        // debounce
+        Timer::after_millis(50).await;
+    }
+}
@@ -1,13 +1,15 @@
 use crate::include_flash_bytes;
+use crate::settings::PowerManagement;
 use core::marker::PhantomData;
-use cyw43::{Control, JoinOptions, NetDriver, Runner};
+use cyw43::{Control, JoinOptions, NetDriver, PowerManagementMode, Runner};
 use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
-use defmt::info;
+use defmt::{Debug2Format, info, warn};
 use embassy_rp::Peri;
 use embassy_rp::dma::Channel;
 use embassy_rp::gpio::{Level, Output, Pin};
 use embassy_rp::peripherals::{DMA_CH0, PIO0};
 use embassy_rp::pio::{Common, Instance, Irq, PioPin, StateMachine};
+use embassy_time::{Duration, with_timeout};
 use static_cell::StaticCell;
 
 #[embassy_executor::task]
@@ -57,33 +59,56 @@ impl<'d, T: Instance, const SM: usize, DMA: Channel> Cyw43<'d, T, SM, DMA> {
         )
     }
 
-    pub async fn init(&mut self) {
+    pub async fn init(&mut self, power_management: PowerManagement) {
         let clm = include_flash_bytes!("firmware/43439A0_clm.bin");
         self.control.init(clm).await;
 
-        self.control
-            .set_power_management(cyw43::PowerManagementMode::PowerSave)
-            .await;
+        let mode = match power_management {
+            PowerManagement::None => PowerManagementMode::None,
+            PowerManagement::PowerSave => PowerManagementMode::PowerSave,
+            PowerManagement::Performance => PowerManagementMode::Performance,
+            PowerManagement::Aggressive => PowerManagementMode::Aggressive,
+            PowerManagement::SuperSave => PowerManagementMode::SuperSave,
+        };
+        self.control.set_power_management(mode).await;
     }
 
-    #[allow(unused)]
-    pub async fn join_wifi(&mut self, ssid: &str, password: &str) {
-        loop {
-            match self
-                .control
-                .join(ssid, JoinOptions::new(password.as_bytes()))
-                .await
-            {
-                Ok(_) => break,
-                Err(err) => {
-                    info!("join failed with status={}", err.status);
+    /// Retries joining until it succeeds or the timeout has passed
+    pub async fn join_wifi(&mut self, ssid: &str, password: &str, timeout: Duration) -> bool {
+        let join = async {
+            loop {
+                match self
+                    .control
+                    .join(ssid, JoinOptions::new(password.as_bytes()))
+                    .await
+                {
+                    Ok(_) => break,
+                    Err(err) => {
+                        info!("join failed with status={}", err.status);
+                    }
                 }
             }
+        };
+        if with_timeout(timeout, join).await.is_ok() {
+            return true;
         }
+        self.leave().await;
+        false
     }
 
-    pub async fn create_ap(&mut self, ssid: &str, password: &str) {
-        self.control.start_ap_wpa2(ssid, password, 5).await;
+    pub async fn leave(&mut self) {
+        self.control.leave().await;
+    }
+
+    /// Lets frames for the multicast address through the chip's filter
+    pub async fn add_multicast_address(&mut self, address: [u8; 6]) {
+        if let Err(err) = self.control.add_multicast_address(address).await {
+            warn!("adding multicast address failed: {}", Debug2Format(&err));
+        }
+    }
+
+    pub async fn create_ap(&mut self, ssid: &str, password: &str, channel: u8) {
+        self.control.start_ap_wpa2(ssid, password, channel).await;
     }
 
     pub async fn set_led(&mut self, value: bool) {
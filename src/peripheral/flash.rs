@@ -0,0 +1,43 @@
+use core::cell::RefCell;
+use embassy_rp::flash::{Blocking, Error, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::once_lock::OnceLock;
+
+pub use embassy_rp::flash::ERASE_SIZE;
+
+/// A Pico 2 has 4 MiB, see `memory.x` for the layout
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+pub const FLASH_BASE: u32 = 0x1000_0000;
+
+pub type FlashDriver = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, RefCell<FlashDriver>>;
+
+static FLASH: OnceLock<SharedFlash> = OnceLock::new();
+
+pub fn init(flash: FlashDriver) {
+    FLASH.init(Mutex::new(RefCell::new(flash))).ok();
+}
+
+/// The driver behind its lock, for users like the firmware updater that need a [SharedFlash]
+pub fn shared() -> &'static SharedFlash {
+    FLASH.try_get().expect("flash not initialized")
+}
+
+fn with_flash<R>(f: impl FnOnce(&mut FlashDriver) -> Result<R, Error>) -> Result<R, Error> {
+    shared().lock(|flash| f(&mut flash.borrow_mut()))
+}
+
+/// All offsets are relative to [FLASH_BASE]
+pub fn read(offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+    with_flash(|flash| flash.blocking_read(offset, buf))
+}
+
+pub fn erase(from: u32, to: u32) -> Result<(), Error> {
+    with_flash(|flash| flash.blocking_erase(from, to))
+}
+
+pub fn write(offset: u32, data: &[u8]) -> Result<(), Error> {
+    with_flash(|flash| flash.blocking_write(offset, data))
+}
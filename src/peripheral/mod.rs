@@ -1,2 +1,5 @@
+pub mod button;
 pub mod cyw43;
+pub mod flash;
 pub mod servo;
+pub mod watchdog;
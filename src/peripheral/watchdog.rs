@@ -0,0 +1,133 @@
+use core::cell::Cell;
+use defmt::{Format, error, info};
+use embassy_rp::watchdog::{ResetReason, Watchdog};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+use serde::Serialize;
+
+pub use robodog_core::api::{ResetCause, Supervised};
+
+const SUPERVISED: [Supervised; 3] = [Supervised::Servo, Supervised::Net, Supervised::Cyw43];
+
+#[derive(Copy, Clone, Debug, Format, Serialize)]
+pub struct Report {
+    pub reset: ResetCause,
+    /// Time since each [Supervised] task last checked in
+    pub silent_ms: [u32; SUPERVISED.len()],
+}
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+pub const FEED_INTERVAL: Duration = Duration::from_millis(250);
+/// A task that has not checked in for this long counts as hung
+const LIVENESS: Duration = Duration::from_secs(3);
+/// The watchdog is fed without supervision while booting, a boot that takes longer is hung
+const BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Scratch register surviving the watchdog reset, holds the hung task
+const SCRATCH: usize = 0;
+const SCRATCH_MAGIC: u32 = 0xD06_0000;
+
+static LAST_SEEN: [AtomicU32; SUPERVISED.len()] = [const { AtomicU32::new(0) }; SUPERVISED.len()];
+static REBOOT: AtomicBool = AtomicBool::new(false);
+static SUPERVISING: AtomicBool = AtomicBool::new(false);
+static RESET_CAUSE: Mutex<CriticalSectionRawMutex, Cell<ResetCause>> =
+    Mutex::new(Cell::new(ResetCause::PowerOn));
+
+fn now_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+pub fn check_in(task: Supervised) {
+    LAST_SEEN[task as usize].store(now_ms(), Ordering::Relaxed);
+}
+
+/// Starts checking that the [Supervised] tasks check in, once all of them are running
+pub fn supervise() {
+    for task in SUPERVISED {
+        check_in(task);
+    }
+    SUPERVISING.store(true, Ordering::Relaxed);
+}
+
+/// Resets the chip on the next feed, giving the caller time to respond
+pub fn reboot() {
+    REBOOT.store(true, Ordering::Relaxed);
+}
+
+pub fn report() -> Report {
+    let now = now_ms();
+    Report {
+        reset: RESET_CAUSE.lock(Cell::get),
+        silent_ms: SUPERVISED
+            .map(|t| now.wrapping_sub(LAST_SEEN[t as usize].load(Ordering::Relaxed))),
+    }
+}
+
+/// All supervised tasks checked in recently
+pub fn healthy() -> bool {
+    stale().is_none()
+}
+
+fn stale() -> Option<Supervised> {
+    let now = now_ms();
+    SUPERVISED.into_iter().find(|t| {
+        now.wrapping_sub(LAST_SEEN[*t as usize].load(Ordering::Relaxed))
+            > LIVENESS.as_millis() as u32
+    })
+}
+
+fn read_reset_cause(watchdog: &mut Watchdog) -> ResetCause {
+    let scratch = watchdog.get_scratch(SCRATCH);
+    watchdog.set_scratch(SCRATCH, 0);
+
+    match watchdog.reset_reason() {
+        None => ResetCause::PowerOn,
+        Some(ResetReason::Forced) => ResetCause::Forced,
+        Some(ResetReason::TimedOut) => {
+            let task = match scratch ^ SCRATCH_MAGIC {
+                0 => Some(Supervised::Servo),
+                1 => Some(Supervised::Net),
+                2 => Some(Supervised::Cyw43),
+                _ => None,
+            };
+            ResetCause::Watchdog(task)
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn watchdog_task(mut watchdog: Watchdog) -> ! {
+    let cause = read_reset_cause(&mut watchdog);
+    info!("Reset cause: {}", cause);
+    RESET_CAUSE.lock(|c| c.set(cause));
+
+    let boot_deadline = Instant::now() + BOOT_TIMEOUT;
+    watchdog.pause_on_debug(true);
+    watchdog.start(TIMEOUT);
+
+    loop {
+        if REBOOT.load(Ordering::Relaxed) {
+            info!("Rebooting");
+            watchdog.trigger_reset();
+        }
+        if !SUPERVISING.load(Ordering::Relaxed) {
+            if Instant::now() < boot_deadline {
+                watchdog.feed();
+            } else {
+                error!("Boot did not finish, waiting for watchdog reset");
+            }
+            Timer::after(FEED_INTERVAL).await;
+            continue;
+        }
+        match stale() {
+            None => watchdog.feed(),
+            Some(task) => {
+                error!("{} stopped checking in, waiting for watchdog reset", task);
+                watchdog.set_scratch(SCRATCH, SCRATCH_MAGIC | task as u32);
+            }
+        }
+        Timer::after(FEED_INTERVAL).await;
+    }
+}
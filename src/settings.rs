@@ -0,0 +1,57 @@
+use crate::peripheral::flash::{self, ERASE_SIZE, FLASH_BASE};
+use defmt::{info, warn};
+
+pub use robodog_core::settings::{PowerManagement, Settings, SettingsError, Station};
+
+/// Last sector of the flash, see `SETTINGS` in `memory.x`
+const SETTINGS_OFFSET: u32 = 0x103F_F000 - FLASH_BASE;
+const MAGIC: u32 = 0x5244_4346;
+const HEADER_SIZE: usize = 8;
+
+/// Reads the settings from flash, falls back to defaults if none are stored or they are invalid
+pub fn load() -> Settings {
+    let mut buf = [0; ERASE_SIZE];
+    if flash::read(SETTINGS_OFFSET, &mut buf).is_err() {
+        warn!("Reading settings failed, using defaults");
+        return Settings::default();
+    }
+
+    let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    let len = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+    if magic != MAGIC || len > ERASE_SIZE - HEADER_SIZE {
+        info!("No settings stored, using defaults");
+        return Settings::default();
+    }
+
+    match serde_json_core::from_slice::<Settings>(&buf[HEADER_SIZE..HEADER_SIZE + len]) {
+        Ok((settings, _)) if settings.validate().is_ok() => settings,
+        _ => {
+            warn!("Stored settings are invalid, using defaults");
+            Settings::default()
+        }
+    }
+}
+
+/// Validates and writes the settings to flash, they are applied on the next boot
+pub fn save(settings: &Settings) -> Result<(), SettingsError> {
+    settings.validate()?;
+    let mut buf = [0xFF; ERASE_SIZE];
+    let len = serde_json_core::to_slice(settings, &mut buf[HEADER_SIZE..])
+        .map_err(|_| SettingsError::TooLarge)?;
+    buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+
+    flash::erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)
+        .and_then(|()| flash::write(SETTINGS_OFFSET, &buf))
+        .map_err(|_| SettingsError::Flash)?;
+    info!("Settings saved");
+    Ok(())
+}
+
+/// Erases the stored settings, the defaults apply on the next boot
+pub fn reset() -> Result<(), SettingsError> {
+    flash::erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)
+        .map_err(|_| SettingsError::Flash)?;
+    info!("Settings reset to defaults");
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Packs a directory into an asset bundle for `POST /api/v1/assets`, see `src/assets/table.rs`.
+//!
+//! ```text
+//! asset-bundle <dir> <out>
+//! curl -X POST --data-binary @<out> -H "Authorization: Bearer $TOKEN" http://robodog.local/api/v1/assets
+//! ```
+
+#[allow(dead_code)]
+#[path = "../../../src/crc32.rs"]
+mod crc32;
+#[allow(dead_code)]
+#[path = "../../../src/assets/table.rs"]
+mod table;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+const MAX_SIZE: usize = 512 * 1024 - 4 * 1024;
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    process::exit(1)
+}
+
+fn main() -> io::Result<()> {
+    let args = env::args().collect::<Vec<_>>();
+    let [_, dir, out] = args.as_slice() else {
+        eprintln!("usage: asset-bundle <dir> <out>");
+        process::exit(2)
+    };
+    let dir = Path::new(dir);
+
+    let mut files = Vec::new();
+    collect(dir, &mut files)?;
+    files.sort();
+    let mut contents = Vec::new();
+    for file in &files {
+        let name = file.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/");
+        let content = fs::read(file)?;
+        println!("{name}: {} bytes, {}", content.len(), table::content_type(&name));
+        contents.push((name, content));
+    }
+
+    let bundle = table::build(&contents, gzip).unwrap_or_else(|message| fail(message));
+    if bundle.len() > MAX_SIZE {
+        fail(format!("bundle is {} bytes, at most {MAX_SIZE} fit", bundle.len()));
+    }
+
+    fs::write(out, &bundle)?;
+    println!("{} files, {} bytes", files.len(), bundle.len());
+    Ok(())
+}
@@ -0,0 +1,426 @@
+//! The routes of `AppProps`, answered from the simulated robot
+
+use crate::http::{self, Request, Response};
+use crate::robot::{EStopMode, EStopSource, Robot, SafeAction};
+use crate::stream;
+use crate::table;
+use robodog_core::api::{
+    Accepted, ApiError, ErrorBody, Firmware, FirmwareState, Hostname, Joints, Login, NetworkInfo,
+    NetworkState, Position, ResetCause, Session, SettingsUpdate, SettingsView, State, WifiMode,
+};
+use robodog_core::auth::{self, Auth, TOKEN_LEN, Token};
+use robodog_core::command::{CommandError, ServoTask};
+use robodog_core::servo::LEG_COUNT;
+use robodog_core::settings::{Settings, Station};
+use serde::de::DeserializeOwned;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddrV4};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fs, io};
+
+/// Captive portal probes, redirected to the web UI like in access point mode
+const CONNECTIVITY_CHECKS: [&str; 9] = [
+    "/generate_204",
+    "/gen_204",
+    "/hotspot-detect.html",
+    "/library/test/success.html",
+    "/connecttest.txt",
+    "/ncsi.txt",
+    "/redirect",
+    "/canonical.html",
+    "/success.txt",
+];
+
+pub fn error(status: u16, message: &str) -> Response {
+    Response::json(status, &ErrorBody { error: message })
+}
+
+/// Answers with the status code and message `robodog_core::api` has for `err`
+fn reject(err: impl Into<ApiError>) -> Response {
+    let err = err.into();
+    Response::json(err.status, &err.body())
+}
+
+/// Not cryptographically secure, good enough for a simulator on localhost
+fn random_token() -> [u8; TOKEN_LEN] {
+    let state = RandomState::new();
+    let mut token = [0; TOKEN_LEN];
+    for (i, chunk) in token.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    token
+}
+
+// only printed through `Debug`
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Report {
+    reset: ResetCause,
+    /// Time since the control loop last ticked, the network tasks don't exist here
+    silent_ms: [u32; 1],
+}
+
+/// Parses the path segment at `i`, `None` if it is missing or doesn't fit the route
+fn segment<T: FromStr>(segments: &[&str], i: usize) -> Option<T> {
+    segments.get(i)?.parse().ok()
+}
+
+fn body<T: DeserializeOwned>(request: &Request) -> Result<T, Response> {
+    serde_json::from_slice(&request.body).map_err(|_| error(400, "invalid request body"))
+}
+
+pub struct App {
+    robot: Arc<Robot>,
+    auth: Mutex<Auth<IpAddr>>,
+    settings: Mutex<Settings>,
+    www: PathBuf,
+    address: SocketAddrV4,
+}
+
+impl App {
+    pub fn new(robot: Arc<Robot>, www: PathBuf, address: SocketAddrV4) -> Self {
+        let settings = Settings::default();
+        let mut auth = Auth::new();
+        auth.set_password(settings.password());
+        App {
+            robot,
+            auth: Mutex::new(auth),
+            settings: Mutex::new(settings),
+            www,
+            address,
+        }
+    }
+
+    /// Rejects requests without a valid session, returns the token otherwise
+    fn authorized(&self, request: &Request) -> Result<String, Response> {
+        let token = auth::token(request.header("Authorization"), request.header("Cookie"));
+        match token {
+            Some(token) if self.auth.lock().unwrap().is_valid(token, request.peer) => {
+                Ok(token.to_string())
+            }
+            _ => Err(reject(ApiError::UNAUTHORIZED)),
+        }
+    }
+
+    fn submit(&self, task: ServoTask) -> Response {
+        match self.robot.submit(task) {
+            Ok(seq) => Response::debug(200, &seq),
+            Err(err @ CommandError::EStopped) => Response::debug(409, &err),
+            Err(err) => Response::debug(503, &err),
+        }
+    }
+
+    fn accept(&self, task: ServoTask) -> Response {
+        match self.robot.submit(task) {
+            Ok(seq) => Response::json(202, &Accepted { seq }),
+            Err(err) => reject(err),
+        }
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        match self.route(request) {
+            Ok(response) | Err(response) => response,
+        }
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, Response> {
+        let segments = request
+            .path
+            .trim_start_matches('/')
+            .split('/')
+            .collect::<Vec<_>>();
+        let method = request.method.as_str();
+        let get = method == "GET" || method == "HEAD";
+
+        let response = match (method, segments.as_slice()) {
+            (_, [""]) if get => Response::redirect("/index.html"),
+            _ if get && CONNECTIVITY_CHECKS.contains(&request.path.as_str()) => {
+                Response::redirect(&format!("http://{}/index.html", self.address))
+            }
+            (_, ["pos", ..]) if get && segments.len() == 4 => {
+                let (Some(x), Some(y), Some(z)) = (
+                    segment::<i16>(&segments, 1),
+                    segment::<i16>(&segments, 2),
+                    segment::<i16>(&segments, 3),
+                ) else {
+                    return self.static_file(request);
+                };
+                self.authorized(request)?;
+                self.submit(ServoTask::MOVE(
+                    x as f32 / 100.0,
+                    y as f32 / 100.0,
+                    z as f32 / 100.0,
+                ))
+            }
+            (_, ["pwm", ..]) if get && segments.len() == 3 => {
+                let (Some(servo), Some(pwm)) =
+                    (segment::<u8>(&segments, 1), segment::<u16>(&segments, 2))
+                else {
+                    return self.static_file(request);
+                };
+                self.authorized(request)?;
+                self.submit(ServoTask::CALIBRATION(servo, pwm as f32 / 6666.66))
+            }
+            (_, ["home"]) if get => {
+                self.authorized(request)?;
+                self.submit(ServoTask::HOME)
+            }
+            (_, ["relax"]) if get => {
+                self.authorized(request)?;
+                self.submit(ServoTask::RELAX)
+            }
+            // stopping is always allowed, without a session
+            (_, ["stop"]) if get => self.submit(ServoTask::STOP),
+            (_, ["estop"]) if get => {
+                self.robot
+                    .trigger_estop(EStopSource::Http, EStopMode::Freeze);
+                Response::debug(200, &self.robot.estop())
+            }
+            (_, ["estop", "relax"]) if get => {
+                self.robot
+                    .trigger_estop(EStopSource::Http, EStopMode::Relax);
+                Response::debug(200, &self.robot.estop())
+            }
+            (_, ["estop", "reset"]) if get => {
+                self.authorized(request)?;
+                self.robot.reset_estop();
+                Response::debug(200, &self.robot.estop())
+            }
+            (_, ["status"]) if get => Response::debug(200, &self.robot.status()),
+            (_, ["watchdog"]) if get => Response::debug(
+                200,
+                &Report {
+                    reset: ResetCause::PowerOn,
+                    silent_ms: [self.robot.silent_ms()],
+                },
+            ),
+            (_, ["heartbeat"]) if get => {
+                self.authorized(request)?;
+                self.robot.heartbeat();
+                Response::debug(200, &self.robot.deadman().last_seen_ms.is_some())
+            }
+            (_, ["deadman"]) if get => Response::debug(200, &self.robot.deadman()),
+            (_, ["deadman", ..]) if get && segments.len() == 3 => {
+                let (Some(timeout_ms), Some(action)) = (
+                    segment::<u32>(&segments, 1),
+                    segment::<SafeAction>(&segments, 2),
+                ) else {
+                    return self.static_file(request);
+                };
+                self.authorized(request)?;
+                self.robot.configure_deadman(timeout_ms, action);
+                Response::debug(200, &self.robot.deadman())
+            }
+            (_, ["cmd", seq]) if get => match seq.parse() {
+                Ok(seq) => Response::debug(200, &self.robot.result(seq)),
+                Err(_) => return self.static_file(request),
+            },
+            (_, ["cmd", seq, "wait"]) if get => match seq.parse() {
+                Ok(seq) => Response::debug(200, &self.robot.wait(seq, Duration::from_secs(5))),
+                Err(_) => return self.static_file(request),
+            },
+            ("POST", ["api", "v1", "pose"]) => {
+                self.authorized(request)?;
+                let pos = body::<Position>(request)?;
+                pos.validate().map_err(reject)?;
+                self.accept(ServoTask::MOVE(pos.x, pos.y, pos.z))
+            }
+            ("POST", ["api", "v1", "joints"]) => {
+                self.authorized(request)?;
+                let joints = body::<Joints>(request)?;
+                joints.validate().map_err(reject)?;
+                self.accept(ServoTask::JOINTS(joints.angles))
+            }
+            ("POST", ["api", "v1", "leg", id]) => {
+                let Ok(id) = id.parse::<u8>() else {
+                    return self.static_file(request);
+                };
+                self.authorized(request)?;
+                let pos = body::<Position>(request)?;
+                if id as usize >= LEG_COUNT {
+                    return Err(reject(CommandError::InvalidLeg));
+                }
+                pos.validate().map_err(reject)?;
+                self.accept(ServoTask::LEG(id, pos.x, pos.y, pos.z))
+            }
+            (_, ["api", "v1", "commands", seq]) if get => {
+                let Ok(seq) = seq.parse::<u32>() else {
+                    return self.static_file(request);
+                };
+                match self.robot.result(seq) {
+                    Some(result) => Response::json(200, &result),
+                    None => error(404, "unknown command"),
+                }
+            }
+            (_, ["api", "v1", "state"]) if get => Response::json(200, &self.state()),
+            (_, ["api", "v1", "network"]) if get => self.network(),
+            ("POST", ["api", "v1", "network", "station"]) => {
+                self.authorized(request)?;
+                let station = body::<Station>(request)?;
+                self.update(|settings| settings.station = Some(station))?;
+                self.network()
+            }
+            ("DELETE", ["api", "v1", "network", "station"]) => {
+                self.authorized(request)?;
+                self.update(|settings| settings.station = None)?;
+                self.network()
+            }
+            ("POST", ["api", "v1", "network", "hostname"]) => {
+                self.authorized(request)?;
+                let hostname = body::<Hostname>(request)?;
+                self.update(|settings| settings.hostname = Some(hostname.hostname))?;
+                self.network()
+            }
+            (_, ["api", "v1", "settings"]) if get => self.settings(),
+            ("POST", ["api", "v1", "settings"]) => {
+                self.authorized(request)?;
+                let update = body::<SettingsUpdate>(request)?;
+                self.update(|settings| update.apply(settings))?;
+                self.settings()
+            }
+            ("DELETE", ["api", "v1", "settings"]) => {
+                self.authorized(request)?;
+                let settings = Settings::default();
+                self.auth.lock().unwrap().set_password(settings.password());
+                *self.settings.lock().unwrap() = settings;
+                println!("Settings reset to defaults");
+                self.settings()
+            }
+            ("POST", ["api", "v1", "reboot"]) => {
+                self.authorized(request)?;
+                self.reboot();
+                Response::empty(202)
+            }
+            ("POST" | "DELETE", ["api", "v1", "assets"]) => {
+                self.authorized(request)?;
+                error(501, "asset uploads are not simulated, edit www/ instead")
+            }
+            (_, ["api", "v1", "firmware"]) if get => Response::json(
+                200,
+                &Firmware {
+                    version: env!("CARGO_PKG_VERSION"),
+                    state: FirmwareState::Confirmed,
+                },
+            ),
+            ("POST", ["api", "v1", "firmware", _]) => {
+                self.authorized(request)?;
+                error(501, "firmware updates are not simulated")
+            }
+            ("POST", ["api", "v1", "login"]) => {
+                let login = body::<Login>(request)?;
+                let token = self.login(request.peer, &login.password)?;
+                Response::json(200, &Session { token })
+            }
+            ("POST", ["api", "v1", "logout"]) => {
+                let token = self.authorized(request)?;
+                self.auth.lock().unwrap().logout(&token);
+                Response::empty(204)
+            }
+            (_, ["api", "v1", "session"]) if get => {
+                self.authorized(request)?;
+                Response::empty(204)
+            }
+            (_, ["events"]) if get => stream::events(&self.robot, stream::DEFAULT_INTERVAL_MS),
+            (_, ["events", interval_ms]) if get => match interval_ms.parse() {
+                Ok(interval_ms) => stream::events(&self.robot, interval_ms),
+                Err(_) => return self.static_file(request),
+            },
+            (_, ["ws"]) if get => {
+                self.authorized(request)?;
+                let robot = self.robot.clone();
+                http::upgrade(request, move |socket| stream::teleop(robot, socket))
+                    .unwrap_or_else(|| error(400, "not a websocket upgrade"))
+            }
+            _ => return self.static_file(request),
+        };
+        Ok(response)
+    }
+
+    /// Validates and stores the changed settings, they apply on the next reboot
+    fn update(&self, f: impl FnOnce(&mut Settings)) -> Result<(), Response> {
+        let mut stored = self.settings.lock().unwrap();
+        let mut settings = stored.clone();
+        f(&mut settings);
+        settings.validate().map_err(reject)?;
+        self.auth.lock().unwrap().set_password(settings.password());
+        *stored = settings;
+        println!("Settings saved");
+        Ok(())
+    }
+
+    /// Clears the sessions, everything else only matters on the robot
+    fn reboot(&self) {
+        let mut auth = Auth::new();
+        auth.set_password(self.settings.lock().unwrap().password());
+        *self.auth.lock().unwrap() = auth;
+        println!("Rebooted, sessions cleared");
+    }
+
+    fn network_info(&self) -> Option<NetworkInfo> {
+        Some(NetworkInfo {
+            mode: WifiMode::Station,
+            address: self.address.ip().octets(),
+        })
+    }
+
+    fn state(&self) -> State {
+        State {
+            motion: self.robot.status(),
+            estop: self.robot.estop(),
+            deadman: self.robot.deadman().into(),
+            reset: ResetCause::PowerOn,
+            network: self.network_info(),
+            uptime_ms: self.robot.uptime_ms(),
+        }
+    }
+
+    fn network(&self) -> Response {
+        let settings = self.settings.lock().unwrap();
+        Response::json(200, &NetworkState::new(&settings, self.network_info()))
+    }
+
+    fn settings(&self) -> Response {
+        Response::json(200, &SettingsView::from(&*self.settings.lock().unwrap()))
+    }
+
+    /// Checks the password like the robot, with the uptime as its clock
+    fn login(&self, peer: IpAddr, password: &str) -> Result<Token, Response> {
+        let mut auth = self.auth.lock().unwrap();
+        match auth.login(peer, password, self.robot.uptime_ms(), random_token()) {
+            Ok(token) => {
+                println!("Login from {peer}, {} sessions", auth.sessions());
+                Ok(token)
+            }
+            Err(err) => {
+                println!("Login from {peer} failed: {err:?}");
+                Err(reject(err))
+            }
+        }
+    }
+
+    /// Serves `www/` from disk, so changes show up without a restart
+    fn static_file(&self, request: &Request) -> Result<Response, Response> {
+        let path = Path::new(request.path.trim_start_matches('/'));
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(Response::empty(404));
+        }
+        let data = match fs::read(self.www.join(path)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Response::empty(404));
+            }
+            Err(err) => return Err(error(500, &err.to_string())),
+        };
+        if !matches!(request.method.as_str(), "GET" | "HEAD") {
+            return Err(Response::empty(405));
+        }
+        Ok(Response::new(200, table::content_type(&request.path), data)
+            .with_header("Cache-Control", "no-cache"))
+    }
+}
@@ -0,0 +1,366 @@
+//! Minimal HTTP/1.1 server with a thread per connection, plus the WebSocket framing
+
+use serde::Serialize;
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Larger bodies are rejected, the robot only takes uploads as streams
+const MAX_BODY: usize = 64 * 1024;
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of open connections, stands in for the clients seen on the network
+pub fn connections() -> usize {
+    CONNECTIONS.load(Ordering::Relaxed)
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Address of the client, logins are locked per address
+    pub peer: IpAddr,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Takes over the connection once the response head has been written
+type Stream = Box<dyn FnOnce(TcpStream) -> io::Result<()> + Send>;
+
+enum Body {
+    Bytes(Vec<u8>),
+    Stream(Stream),
+}
+
+pub struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: Body::Bytes(body.into()),
+        }
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    pub fn json(status: u16, value: &impl Serialize) -> Self {
+        Response::new(
+            status,
+            "application/json",
+            serde_json::to_vec(value).unwrap(),
+        )
+    }
+
+    /// Plain text `Debug` output like picoserve's `DebugValue`
+    pub fn debug(status: u16, value: &impl Debug) -> Self {
+        Response::new(status, "text/plain; charset=utf-8", format!("{value:?}"))
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Response::empty(303).with_header("Location", location)
+    }
+
+    pub fn stream(
+        status: u16,
+        f: impl FnOnce(TcpStream) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Stream(Box::new(f)),
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    /// Writes the response, returns the stream if the connection can be kept open
+    fn write(self, mut stream: TcpStream, head_only: bool) -> io::Result<Option<TcpStream>> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head += &format!("{name}: {value}\r\n");
+        }
+        match self.body {
+            Body::Bytes(body) => {
+                head += &format!("Content-Length: {}\r\n\r\n", body.len());
+                stream.write_all(head.as_bytes())?;
+                if !head_only {
+                    stream.write_all(&body)?;
+                }
+                Ok(Some(stream))
+            }
+            Body::Stream(f) => {
+                head += "\r\n";
+                stream.write_all(head.as_bytes())?;
+                f(stream)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        303 => "See Other",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::ErrorKind::InvalidData.into());
+    };
+    // the robot ignores query strings as well
+    let path = target.split('?').next().unwrap_or_default();
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        peer: reader.get_ref().peer_addr()?.ip(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let len = match request.header("Content-Length") {
+        Some(len) => len.parse().map_err(|_| io::ErrorKind::InvalidData)?,
+        None => 0,
+    };
+    if len > MAX_BODY {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    request.body.resize(len, 0);
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    handler: &(dyn Fn(&Request) -> Response + Sync),
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    while let Some(request) = read_request(&mut reader)? {
+        let head_only = request.method == "HEAD";
+        let close = request
+            .header("Connection")
+            .is_some_and(|c| c.eq_ignore_ascii_case("close"));
+        let response = handler(&request);
+        match response.write(stream.try_clone()?, head_only)? {
+            Some(_) if !close => {}
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+pub fn serve(
+    listener: TcpListener,
+    handler: Arc<dyn Fn(&Request) -> Response + Send + Sync>,
+) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let handler = handler.clone();
+        thread::spawn(move || {
+            CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+            // clients going away is expected, the other errors are worth seeing
+            if let Err(err) = serve_connection(stream, handler.as_ref())
+                && !matches!(
+                    err.kind(),
+                    io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::UnexpectedEof
+                )
+            {
+                println!("Connection closed: {err}");
+            }
+            CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+    Ok(())
+}
+
+/// Writes one server sent event
+pub fn write_event(stream: &mut TcpStream, name: &str, data: &str) -> io::Result<()> {
+    write!(stream, "event: {name}\ndata: {data}\n\n")?;
+    stream.flush()
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Answers the upgrade request and hands the connection to `f`
+pub fn upgrade(
+    request: &Request,
+    f: impl FnOnce(TcpStream) -> io::Result<()> + Send + 'static,
+) -> Option<Response> {
+    let key = request.header("Sec-WebSocket-Key")?;
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WS_GUID.as_bytes());
+    Some(
+        Response::stream(101, f)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", base64(&sha1.digest().bytes())),
+    )
+}
+
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong,
+    Close,
+}
+
+/// Reads one message from the client, continuation frames are not supported
+pub fn read_message(stream: &mut impl Read) -> io::Result<Message> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0F;
+    let mut len = (head[1] & 0x7F) as u64;
+    if len == 126 {
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf)?;
+        len = u16::from_be_bytes(buf) as u64;
+    } else if len == 127 {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf)?;
+        len = u64::from_be_bytes(buf);
+    }
+    if len > MAX_BODY as u64 {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let mut mask = [0; 4];
+    if head[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data)?;
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+
+    match opcode {
+        0x1 => String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| io::ErrorKind::InvalidData.into()),
+        0x2 => Ok(Message::Binary(data)),
+        0x8 => Ok(Message::Close),
+        0x9 => Ok(Message::Ping(data)),
+        0xA => Ok(Message::Pong),
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, data: &[u8]) -> io::Result<()> {
+    let mut head = vec![0x80 | opcode];
+    match data.len() {
+        len @ 0..=125 => head.push(len as u8),
+        len @ 126..=0xFFFF => {
+            head.push(126);
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            head.push(127);
+            head.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    stream.write_all(&head)?;
+    stream.write_all(data)
+}
+
+pub fn send_text(stream: &mut impl Write, text: &str) -> io::Result<()> {
+    write_frame(stream, 0x1, text.as_bytes())
+}
+
+pub fn send_pong(stream: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    write_frame(stream, 0xA, data)
+}
+
+/// Sends a close frame with an optional status code and reason
+pub fn close(stream: &mut impl Write, reason: Option<(u16, &str)>) -> io::Result<()> {
+    let mut data = Vec::new();
+    if let Some((code, text)) = reason {
+        data.extend_from_slice(&code.to_be_bytes());
+        data.extend_from_slice(text.as_bytes());
+    }
+    write_frame(stream, 0x8, &data)
+}
@@ -0,0 +1,60 @@
+//! Runs the robot's control loop against simulated servos and serves the same HTTP API and
+//! web UI as the firmware, so the frontend and clients can be developed without the robot.
+//!
+//! ```text
+//! simulator [port] [www dir]
+//! ```
+//!
+//! Defaults to port 8080 and the `www/` directory of the repository. The password for the
+//! control routes is the default access point password.
+
+#[allow(dead_code)]
+#[path = "../../../src/crc32.rs"]
+mod crc32;
+#[allow(dead_code)]
+#[path = "../../../src/assets/table.rs"]
+mod table;
+
+mod app;
+mod http;
+mod robot;
+mod stream;
+
+use crate::app::App;
+use crate::robot::Robot;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::{env, process, thread};
+
+fn usage() -> ! {
+    eprintln!("usage: simulator [port] [www dir]");
+    process::exit(2)
+}
+
+fn main() -> std::io::Result<()> {
+    let args = env::args().collect::<Vec<_>>();
+    let port = match args.get(1) {
+        Some(port) => port.parse().unwrap_or_else(|_| usage()),
+        None => 8080,
+    };
+    let www = match args.get(2) {
+        Some(www) => PathBuf::from(www),
+        None => PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../../www")),
+    };
+    if args.len() > 3 {
+        usage()
+    }
+
+    let robot = Arc::new(Robot::new());
+    thread::spawn({
+        let robot = robot.clone();
+        move || robot.run()
+    });
+
+    let address = SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+    let listener = TcpListener::bind(address)?;
+    let app = Arc::new(App::new(robot, www, address));
+    println!("Serving on http://{address}/");
+    http::serve(listener, Arc::new(move |request| app.handle(request)))
+}
@@ -0,0 +1,222 @@
+//! The robot's control loop from `robodog_core::control` driving [Legs] on simulated PWM
+//! outputs, with a thread and a condition variable where `servo_task` uses embassy.
+
+use robodog_core::command::{CommandError, CommandResult, ServoTask};
+use robodog_core::control::{ControlLoop, Scheduler, Step, TICK_MS};
+pub use robodog_core::control::{
+    Deadman, EStop, EStopMode, EStopSource, MotionEvent, SafeAction, Status,
+};
+use robodog_core::legs::Legs;
+use robodog_core::servo::{Servo, ServoConfig};
+use robodog_core::telemetry::Recorder;
+pub use robodog_core::telemetry::Telemetry;
+use std::convert::Infallible;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Stands in for a PWM slice output, 16 bit like the RP2350 ones
+pub struct SimPwm;
+
+impl embedded_hal::pwm::ErrorType for SimPwm {
+    type Error = Infallible;
+}
+
+impl embedded_hal::pwm::SetDutyCycle for SimPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, _duty: u16) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+struct State {
+    scheduler: Scheduler,
+    telemetry: Recorder,
+    last_tick: Option<Instant>,
+    /// Set when the control loop has to run before its next tick
+    woken: bool,
+}
+
+pub struct Robot {
+    state: Mutex<State>,
+    woken: Condvar,
+    subscribers: Mutex<Vec<Sender<MotionEvent>>>,
+    boot: Instant,
+}
+
+impl Robot {
+    pub fn new() -> Self {
+        Robot {
+            state: Mutex::new(State {
+                scheduler: Scheduler::new(),
+                telemetry: Recorder::new(),
+                last_tick: None,
+                woken: false,
+            }),
+            woken: Condvar::new(),
+            subscribers: Mutex::new(Vec::new()),
+            boot: Instant::now(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn wake(&self, state: &mut State) {
+        state.woken = true;
+        self.woken.notify_one();
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        self.boot.elapsed().as_millis() as u64
+    }
+
+    /// Queues a task for the control loop and returns its sequence id
+    pub fn submit(&self, task: ServoTask) -> Result<u32, CommandError> {
+        let mut state = self.lock();
+        let seq = state.scheduler.submit(task)?;
+        self.wake(&mut state);
+        Ok(seq)
+    }
+
+    /// Streams a pose target for teleoperation. Only the latest one is kept and it replaces
+    /// the running motion instead of waiting for it to finish.
+    pub fn stream(&self, pose: [f32; 3]) -> Result<(), CommandError> {
+        let now_ms = self.uptime_ms();
+        let mut state = self.lock();
+        state.scheduler.stream(pose, now_ms)?;
+        self.wake(&mut state);
+        Ok(())
+    }
+
+    /// Returns the last known result of a command, `None` if it is unknown or too old
+    pub fn result(&self, seq: u32) -> Option<CommandResult> {
+        self.lock().scheduler.result(seq)
+    }
+
+    /// Waits until the command has finished or the timeout has passed
+    pub fn wait(&self, seq: u32, timeout: Duration) -> Option<CommandResult> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let result = self.result(seq);
+            match result {
+                Some(r) if !r.is_finished() && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.lock().scheduler.status()
+    }
+
+    /// Time since the control loop last ticked
+    pub fn silent_ms(&self) -> u32 {
+        self.lock()
+            .last_tick
+            .map_or(0, |tick| tick.elapsed().as_millis() as u32)
+    }
+
+    pub fn telemetry(&self) -> Telemetry {
+        self.lock().telemetry.snapshot()
+    }
+
+    /// Latches the emergency stop. A later trigger can only escalate from freeze to relax.
+    pub fn trigger_estop(&self, source: EStopSource, mode: EStopMode) {
+        let now_ms = self.uptime_ms();
+        let mut state = self.lock();
+        let latched = state.scheduler.estop().map(|e| e.mode);
+        state.scheduler.trigger_estop(source, mode, now_ms);
+        if state.scheduler.estop().map(|e| e.mode) != latched {
+            println!("Emergency stop from {source:?}: {mode:?}");
+        }
+        self.wake(&mut state);
+    }
+
+    /// Releases the latch, motion commands are accepted again afterwards
+    pub fn reset_estop(&self) {
+        let mut state = self.lock();
+        if state.scheduler.estop().is_some() {
+            println!("Emergency stop reset");
+        }
+        state.scheduler.reset_estop();
+    }
+
+    pub fn estop(&self) -> Option<EStop> {
+        self.lock().scheduler.estop()
+    }
+
+    pub fn deadman(&self) -> Deadman {
+        self.lock().scheduler.deadman()
+    }
+
+    pub fn configure_deadman(&self, timeout_ms: u32, action: SafeAction) {
+        self.lock().scheduler.configure_deadman(timeout_ms, action);
+    }
+
+    /// Extends the lease only if it is armed, so heartbeats alone never start motion supervision
+    pub fn heartbeat(&self) {
+        let now_ms = self.uptime_ms();
+        self.lock().scheduler.heartbeat(now_ms);
+    }
+
+    /// Motion events from now on, until the receiver is dropped
+    pub fn subscribe(&self) -> Receiver<MotionEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn publish(&self, event: MotionEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event).is_ok());
+    }
+
+    /// Runs the control loop with the same waiting as `servo_task` on the robot
+    pub fn run(&self) -> ! {
+        let servos = ServoConfig::calibration().map(|config| Servo::new(SimPwm, config));
+        let mut control = ControlLoop::new(Legs::new(servos));
+        let tick = Duration::from_millis(TICK_MS);
+
+        loop {
+            let now_ms = self.uptime_ms();
+            // like on the robot, IK and PWM run without the lock
+            let plan = control.plan(&mut self.lock().scheduler, now_ms);
+            let report = control.run(plan);
+            {
+                let mut state = self.lock();
+                state.scheduler.record(&report);
+                state.telemetry.record_legs(control.legs());
+            }
+            report.events().for_each(|event| self.publish(event));
+            if report.step() == Step::Busy {
+                continue;
+            }
+
+            // a new command starts right away and the tick after it a full period later
+            let (mut state, wait) = self
+                .woken
+                .wait_timeout_while(self.lock(), tick, |state| !state.woken)
+                .unwrap();
+            if wait.timed_out() {
+                state
+                    .telemetry
+                    .record_tick(self.boot.elapsed().as_micros() as u64);
+                state.last_tick = Some(Instant::now());
+                drop(state);
+                control.tick();
+            } else {
+                state.woken = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Telemetry events on `/events` and pose streaming on `/ws`
+
+use crate::http::{self, Message, Response};
+use crate::robot::{Robot, Telemetry};
+use robodog_core::api::{ErrorBody, Position, StreamCommand, StreamState};
+use robodog_core::legs::in_range;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_INTERVAL_MS: u64 = 200;
+const MIN_INTERVAL_MS: u64 = 20;
+const MAX_INTERVAL_MS: u64 = 5000;
+/// How often the state is pushed to the client and the velocity is integrated
+const STATE_INTERVAL: Duration = Duration::from_millis(50);
+/// The velocity is dropped if the client has been silent for this long
+const VELOCITY_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Serialize)]
+struct TelemetryEvent {
+    telemetry: Telemetry,
+    /// Clients seen on the network within the last minute
+    clients: usize,
+    uptime_ms: u64,
+}
+
+/// Streams telemetry at a fixed rate and motion events as they happen
+pub fn events(robot: &Arc<Robot>, interval_ms: u64) -> Response {
+    let interval = Duration::from_millis(interval_ms.clamp(MIN_INTERVAL_MS, MAX_INTERVAL_MS));
+    let robot = robot.clone();
+    Response::stream(200, move |mut stream| {
+        let motion = robot.subscribe();
+        let mut next = Instant::now();
+        loop {
+            let timeout = next.saturating_duration_since(Instant::now());
+            match motion.recv_timeout(timeout) {
+                Ok(event) => {
+                    let data = serde_json::to_string(&event).unwrap();
+                    http::write_event(&mut stream, "motion", &data)?;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // skip ticks instead of bursting when the client is slow to read
+                    next = (next + interval).max(Instant::now());
+                    let event = TelemetryEvent {
+                        telemetry: robot.telemetry(),
+                        clients: http::connections(),
+                        uptime_ms: robot.uptime_ms(),
+                    };
+                    let data = serde_json::to_string(&event).unwrap();
+                    http::write_event(&mut stream, "telemetry", &data)?;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    })
+    .with_header("Content-Type", "text/event-stream")
+    .with_header("Cache-Control", "no-cache")
+}
+
+/// Pose streaming state of one remote client
+struct Teleop {
+    robot: Arc<Robot>,
+    pose: Option<[f32; 3]>,
+    /// Velocity of the foot target in mm/s, applied relative to the last pose
+    velocity: Option<[f32; 3]>,
+    last_message: Instant,
+}
+
+impl Teleop {
+    /// Has to be called for every message of the client, keeps the deadman lease alive
+    fn received(&mut self) {
+        self.robot.heartbeat();
+        self.last_message = Instant::now();
+    }
+
+    fn set_pose(&mut self, pose: [f32; 3]) -> Result<(), &'static str> {
+        if !in_range(pose) {
+            return Err("coordinates out of range");
+        }
+        self.robot
+            .stream(pose)
+            .map_err(|_| "emergency stop latched")?;
+        self.pose = Some(pose);
+        Ok(())
+    }
+
+    /// Moves the pose along the velocity, does nothing until a pose has been set
+    fn tick(&mut self, dt: Duration) {
+        if self.last_message.elapsed() > VELOCITY_TIMEOUT {
+            self.velocity = None;
+        }
+        let (Some(pose), Some(velocity)) = (self.pose, self.velocity) else {
+            return;
+        };
+
+        let mut next = pose;
+        for (p, v) in next.iter_mut().zip(velocity) {
+            *p += v * dt.as_secs_f32();
+        }
+        if self.set_pose(next).is_err() {
+            self.velocity = None;
+        }
+    }
+}
+
+/// Runs a WebSocket connection, messages are read on their own thread
+pub fn teleop(robot: Arc<Robot>, mut stream: TcpStream) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        while let Ok(message) = http::read_message(&mut reader) {
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut teleop = Teleop {
+        robot,
+        pose: None,
+        velocity: None,
+        last_message: Instant::now(),
+    };
+    let mut last_tick = Instant::now();
+    let mut next = last_tick + STATE_INTERVAL;
+    let close_reason = loop {
+        let data = match rx.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(Message::Text(text)) => text.into_bytes(),
+            Ok(Message::Binary(data)) => data,
+            Ok(Message::Ping(data)) => {
+                http::send_pong(&mut stream, &data)?;
+                continue;
+            }
+            Ok(Message::Pong) => continue,
+            Ok(Message::Close) => break None,
+            Err(RecvTimeoutError::Disconnected) => {
+                println!("Websocket read error");
+                break Some((1002, "Websocket Error"));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                next += STATE_INTERVAL;
+                let now = Instant::now();
+                teleop.tick(now - last_tick);
+                last_tick = now;
+
+                let status = teleop.robot.status();
+                let state = StreamState {
+                    state: status.state,
+                    pose: teleop.pose.map(Position::from_array),
+                    queued: status.queued,
+                    estop: status.estop.is_some(),
+                };
+                http::send_text(&mut stream, &serde_json::to_string(&state).unwrap())?;
+                continue;
+            }
+        };
+
+        teleop.received();
+        let result = match serde_json::from_slice::<StreamCommand>(&data) {
+            Ok(cmd) => {
+                if let Some(velocity) = cmd.velocity {
+                    teleop.velocity = Some(velocity.to_array());
+                }
+                match cmd.pose {
+                    Some(pose) => teleop.set_pose(pose.to_array()),
+                    None => Ok(()),
+                }
+            }
+            Err(_) => Err("invalid command"),
+        };
+        if let Err(error) = result {
+            let body = serde_json::to_string(&ErrorBody { error }).unwrap();
+            http::send_text(&mut stream, &body)?;
+        }
+    };
+
+    http::close(&mut stream, close_reason)?;
+    stream.flush()
+}
@@ -0,0 +1,111 @@
+//! Host side client for the UDP control protocol, sharing the firmware's encoding code through `robodog-core`.
+//!
+//! ```text
+//! udp-client <ip> pose <x> <y> <z>
+//! udp-client <ip> velocity <x> <y> <z>
+//! udp-client <ip> stop
+//! udp-client <ip> watch
+//! ```
+//!
+//! The robot only takes packets other than `stop` from a host that is logged in, log in with
+//! `POST /api/v1/login` from the same machine first.
+
+use robodog_core::protocol::{MAX_PACKET_SIZE, Message, PORT, Packet, SequenceFilter};
+use std::net::UdpSocket;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+const STATES: [&str; 4] = ["idle", "moving", "holding", "relaxed"];
+/// Keeps the deadman lease and the velocity alive while running
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+fn usage() -> ! {
+    eprintln!("usage: udp-client <ip> (pose <x> <y> <z> | velocity <x> <y> <z> | stop | watch)");
+    process::exit(2)
+}
+
+fn parse_vec(args: &[String]) -> [f32; 3] {
+    if args.len() != 3 {
+        usage()
+    }
+    let mut v = [0.0; 3];
+    for (value, arg) in v.iter_mut().zip(args) {
+        *value = arg.parse().unwrap_or_else(|_| usage());
+    }
+    v
+}
+
+fn main() -> std::io::Result<()> {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() < 3 {
+        usage()
+    }
+    let message = match args[2].as_str() {
+        "pose" => Message::Pose(parse_vec(&args[3..])),
+        "velocity" => Message::Velocity(parse_vec(&args[3..])),
+        "stop" => Message::Stop,
+        "watch" => Message::Heartbeat,
+        _ => usage(),
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((args[1].as_str(), PORT))?;
+    socket.set_read_timeout(Some(HEARTBEAT_INTERVAL))?;
+
+    // start from the time, so a restarted client is not taken for stale packets
+    let mut seq = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u32;
+    let mut send = |message| -> std::io::Result<()> {
+        seq = seq.wrapping_add(1);
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let len = Packet::new(seq, message).encode(&mut buf).unwrap();
+        socket.send(&buf[..len]).map(|_| ())
+    };
+    send(message)?;
+    // velocity and watch run until interrupted, pose and stop exit on the first reply
+    let continuous = matches!(message, Message::Velocity(_) | Message::Heartbeat);
+
+    let mut filter = SequenceFilter::new();
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut last_heartbeat = Instant::now();
+    loop {
+        match socket.recv(&mut buf) {
+            Ok(len) => match Packet::decode(&buf[..len]) {
+                Ok(Packet {
+                    seq,
+                    message: Message::State(state),
+                }) if filter.accept(seq) => {
+                    println!(
+                        "#{seq} {} estop={} queued={} pose={:?}",
+                        STATES.get(state.state as usize).unwrap_or(&"unknown"),
+                        state.estop,
+                        state.queued,
+                        state.pose
+                    );
+                    if !continuous {
+                        return Ok(());
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("invalid packet: {err:?}"),
+            },
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => return Err(err),
+        }
+
+        if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            send(if continuous {
+                message
+            } else {
+                Message::Heartbeat
+            })?;
+            last_heartbeat = Instant::now();
+        }
+    }
+}
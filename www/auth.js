@@ -0,0 +1,44 @@
+// Control routes need a session. The token is kept in a cookie, so WebSocket upgrades
+// carry it as well. On the first 401 the user is asked for the password once.
+let login = null;
+
+function ensure_login() {
+    if (!login) {
+        login = new Promise(function(resolve, reject) {
+            const password = prompt("Password");
+            if (password === null) {
+                reject();
+                return;
+            }
+            fetch("/api/v1/login", {
+                method: "POST",
+                headers: { "Content-Type": "application/json" },
+                body: JSON.stringify({ password: password }),
+            }).then(async function(response) {
+                const body = await response.json();
+                if (response.ok) {
+                    document.cookie = "token=" + body.token + "; path=/; SameSite=Strict";
+                    resolve();
+                } else {
+                    alert(body.error);
+                    reject();
+                }
+            }, reject);
+        });
+        // don't ask again right away for requests that were already queued
+        login.then(
+            function() { login = null; },
+            function() { setTimeout(function() { login = null; }, 5000); }
+        );
+    }
+    return login;
+}
+
+async function api(url, options) {
+    let response = await fetch(url, options);
+    if (response.status === 401) {
+        await ensure_login();
+        response = await fetch(url, options);
+    }
+    return response;
+}
@@ -8,5 +8,9 @@ pwm.oninput = function() {
     let motor = document.querySelector('input[name="motor"]:checked').value;
     let index = (leg * 3) + (motor * 1);
     pwm_label.textContent = "PWM: " + (pwm.value / 6666.66);
-    fetch("/pwm/" + index + "/" + pwm.value)
-}
\ No newline at end of file
+    api("/pwm/" + index + "/" + pwm.value)
+}
+
+setInterval(function() {
+    api("/heartbeat")
+}, 250)
\ No newline at end of file
@@ -4,22 +4,71 @@ const z = document.getElementById("z");
 const x_label = document.getElementById("x_label");
 const y_label = document.getElementById("y_label");
 const z_label = document.getElementById("z_label");
+const state = document.getElementById("state");
+
+let socket = null;
 
 x_label.textContent = "X: " + x.value / 100;
 y_label.textContent = "Y: " + y.value / 100;
 z_label.textContent = "Z: " + z.value / 100;
 
+function connect() {
+    api("/api/v1/session").then(open_socket, function() {
+        setTimeout(connect, 1000);
+    });
+}
+
+function open_socket() {
+    const ws = new WebSocket("ws://" + location.host + "/ws");
+    ws.onopen = function() {
+        socket = ws;
+    }
+    ws.onmessage = function(event) {
+        const msg = JSON.parse(event.data);
+        if (msg.error) {
+            state.textContent = "Error: " + msg.error;
+        } else {
+            state.textContent = "State: " + msg.state + (msg.estop ? " (E-STOP)" : "");
+        }
+    }
+    ws.onclose = function() {
+        socket = null;
+        state.textContent = "Disconnected";
+        setTimeout(connect, 1000);
+    }
+}
+
+function send_pos() {
+    if (socket) {
+        socket.send(JSON.stringify({
+            pose: { x: x.value / 100, y: y.value / 100, z: z.value / 100 }
+        }));
+    } else {
+        api("/pos/" + x.value + "/" + y.value + "/" + z.value)
+    }
+}
+
 x.oninput = function() {
     x_label.textContent = "X: " + x.value / 100;
-    fetch("/pos/" + x.value + "/" + y.value + "/" + z.value)
+    send_pos()
 }
 
 y.oninput = function() {
     y_label.textContent = "Y: " + y.value / 100;
-    fetch("/pos/" + x.value + "/" + y.value + "/" + z.value)
+    send_pos()
 }
 
 z.oninput = function() {
     z_label.textContent = "Z: " + z.value / 100;
-    fetch("/pos/" + x.value + "/" + y.value + "/" + z.value)
-}
\ No newline at end of file
+    send_pos()
+}
+
+setInterval(function() {
+    if (socket) {
+        socket.send("{}")
+    } else {
+        api("/heartbeat")
+    }
+}, 250)
+
+connect()
\ No newline at end of file
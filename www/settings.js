@@ -0,0 +1,62 @@
+const form = document.getElementById("settings");
+const message = document.getElementById("message");
+
+function show(settings) {
+    document.getElementById("hostname").value = settings.hostname;
+    document.getElementById("ap_ssid").value = settings.access_point.ssid;
+    document.getElementById("ap_password").value = "";
+    document.getElementById("ap_channel").value = settings.access_point.channel;
+    document.getElementById("ap_address").value = settings.access_point.address.join(".");
+    document.getElementById("ap_prefix_len").value = settings.access_point.prefix_len;
+    document.getElementById("ap_pool_start").value = settings.access_point.pool_start.join(".");
+    document.getElementById("ap_pool_size").value = settings.access_point.pool_size;
+    document.getElementById("ap_lease_time_s").value = settings.access_point.lease_time_s;
+    document.getElementById("power_management").value = settings.power_management;
+}
+
+async function handle(response) {
+    const body = await response.json();
+    if (response.ok) {
+        show(body);
+        message.textContent = "Saved, reboot to apply the network settings";
+    } else {
+        message.textContent = "Error: " + body.error;
+    }
+}
+
+form.onsubmit = function(event) {
+    event.preventDefault();
+    const update = {
+        hostname: document.getElementById("hostname").value,
+        ap_ssid: document.getElementById("ap_ssid").value,
+        ap_channel: Number(document.getElementById("ap_channel").value),
+        ap_address: document.getElementById("ap_address").value.split(".").map(Number),
+        ap_prefix_len: Number(document.getElementById("ap_prefix_len").value),
+        ap_pool_start: document.getElementById("ap_pool_start").value.split(".").map(Number),
+        ap_pool_size: Number(document.getElementById("ap_pool_size").value),
+        ap_lease_time_s: Number(document.getElementById("ap_lease_time_s").value),
+        power_management: document.getElementById("power_management").value,
+    };
+    const password = document.getElementById("ap_password").value;
+    if (password) {
+        update.ap_password = password;
+    }
+    api("/api/v1/settings", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify(update),
+    }).then(handle);
+}
+
+document.getElementById("defaults").onclick = function() {
+    if (confirm("Reset all settings to their defaults?")) {
+        api("/api/v1/settings", { method: "DELETE" }).then(handle);
+    }
+}
+
+document.getElementById("reboot").onclick = function() {
+    api("/api/v1/reboot", { method: "POST" })
+        .then(() => message.textContent = "Rebooting...");
+}
+
+fetch("/api/v1/settings").then(response => response.json()).then(show);